@@ -0,0 +1,18 @@
+create table access_audit
+(
+    id          bigserial primary key,
+    -- shared by requests of one trace, or chosen by client
+    access_id   uuid        not null,
+    remote_ip   varchar     not null,
+    remote_port int4,
+    method      varchar     not null,
+    route       varchar     not null,
+    status      int2        not null,
+    cost_ms     int8        not null,
+    user_id     varchar,
+    finished_at timestamptz not null
+);
+
+create index access_audit_access_id_idx on access_audit (access_id);
+create index access_audit_finished_at_idx on access_audit (finished_at);
+create index access_audit_user_id_idx on access_audit (user_id, finished_at);
@@ -1,22 +1,75 @@
 use std::sync::Arc;
 
+use axum::http::HeaderName;
+
+use crate::scaffold::{
+    access_audit::AccessAudit, access_id::AccessIdSource, access_log::AccessLogConfig,
+    access_rules::AccessRules, body_capture::BodyCapturePolicy, client_info::ClientInfoPolicy,
+    geoip::GeoIp, header_log::HeaderLogPolicy, inflight::InFlightRegistry,
+    remote_addr::RemoteAddrConfig, slow_request::SlowRequestPolicy,
+};
+
 #[derive(Clone)]
 pub struct HostState {
     inner: Arc<HostStateInner>,
 }
 
 struct HostStateInner {
-    remote_header: Option<String>,
+    remote_addr: RemoteAddrConfig,
+    access_log: AccessLogConfig,
 }
 
 impl HostState {
-    pub fn new(remote_header: Option<String>) -> Self {
+    pub fn new(remote_addr: RemoteAddrConfig, access_log: AccessLogConfig) -> Self {
         Self {
-            inner: Arc::new(HostStateInner { remote_header }),
+            inner: Arc::new(HostStateInner {
+                remote_addr,
+                access_log,
+            }),
         }
     }
 
-    pub fn remote_header(&self) -> Option<&str> {
-        self.inner.remote_header.as_deref()
+    pub fn remote_addr(&self) -> &RemoteAddrConfig {
+        &self.inner.remote_addr
+    }
+
+    pub fn access_id_sources(&self) -> &[AccessIdSource] {
+        &self.inner.access_log.access_id_sources
+    }
+
+    pub fn access_id_response_header(&self) -> &HeaderName {
+        &self.inner.access_log.access_id_response_header
+    }
+
+    pub fn slow_request(&self) -> &SlowRequestPolicy {
+        &self.inner.access_log.slow_request
+    }
+
+    pub fn body_capture(&self) -> &BodyCapturePolicy {
+        &self.inner.access_log.body_capture
+    }
+
+    pub fn header_log(&self) -> &HeaderLogPolicy {
+        &self.inner.access_log.header_log
+    }
+
+    pub fn access_rules(&self) -> &AccessRules {
+        &self.inner.access_log.access_rules
+    }
+
+    pub fn geoip(&self) -> &GeoIp {
+        &self.inner.access_log.geoip
+    }
+
+    pub fn client_info(&self) -> &ClientInfoPolicy {
+        &self.inner.access_log.client_info
+    }
+
+    pub fn access_audit(&self) -> Option<&AccessAudit> {
+        self.inner.access_log.access_audit.as_ref()
+    }
+
+    pub fn inflight(&self) -> &InFlightRegistry {
+        &self.inner.access_log.inflight
     }
 }
@@ -3,18 +3,45 @@ mod db_enum;
 mod scaffold;
 mod schema;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
-use anyhow::{Context, Result};
-use axum::{Router, http::StatusCode, routing::get};
+use anyhow::{Context, Result, anyhow};
+use axum::{
+    Router,
+    http::{HeaderName, StatusCode},
+    routing::{delete, get},
+};
 use clap::Parser;
+use ipnet::IpNet;
 use serde::Deserialize;
 use tokio::net::TcpListener;
-use tracing::{Instrument, info, info_span};
+use tracing::{Instrument, info, info_span, warn};
 
 use crate::{
     api::state::HostState,
-    scaffold::{access_log::AccessLog, quit_sig, tracing_output},
+    scaffold::{
+        access_audit::{AccessAudit, AccessAuditOptions},
+        access_id::AccessIdSource,
+        access_log::{AccessLog, AccessLogConfig},
+        access_rules::AccessRules,
+        access_sink::{AccessLogFormat, AccessLogRotation, AccessSinkOptions},
+        body_capture::{BodyCaptureMode, BodyCapturePolicy},
+        client_info::{ClientInfoPolicy, MinAppVersion},
+        client_limit::{ConnectionLimit, RequestLimitConfig, RequestLimitLayer},
+        connection_info::ConnectionInfo,
+        database_init,
+        geoip::GeoIp,
+        header_log::{DEFAULT_DENIED_HEADERS, HeaderLogPolicy},
+        inflight::{self, InFlightRegistry},
+        ip_filter::{IpFilter, IpFilterLayer},
+        ip_privacy::{IP_HEADERS, IpPrivacy, IpPrivacyMode},
+        metrics,
+        proxy_protocol::{ProxyListener, ProxyProtocolMode},
+        quit_sig,
+        remote_addr::{NoRemotePolicy, RemoteAddrConfig},
+        slow_request::{RouteBudget, SlowRequestPolicy},
+        tracing_output,
+    },
 };
 
 #[derive(Parser, Deserialize)]
@@ -26,9 +53,263 @@ struct Opts {
     )]
     bind: SocketAddr,
 
-    #[clap(long = "remote-header", help = "remote header(eg. X-Forward-Ip)")]
+    #[clap(
+        long = "admin-bind",
+        help = "admin routes & /metrics bind addr, without it they are served on api bind only with --admin-ip-filter, otherwise not at all"
+    )]
+    admin_bind: Option<SocketAddr>,
+
+    #[clap(
+        long = "proxy-protocol",
+        default_value = "off",
+        help = "PROXY protocol v1/v2 preamble on connections from trusted proxies: off, optional or strict"
+    )]
+    proxy_protocol: ProxyProtocolMode,
+
+    #[clap(
+        long = "proxy-protocol-timeout-ms",
+        default_value = "3000",
+        help = "max time to read PROXY protocol preamble"
+    )]
+    proxy_protocol_timeout_ms: u64,
+
+    #[clap(
+        long = "remote-header",
+        help = "remote header(eg. X-Forward-Ip, Forwarded as rfc 7239), honored only from --trusted-proxy"
+    )]
     remote_header: Option<String>,
 
+    #[clap(
+        long = "trusted-proxy",
+        help = "proxy cidr allowed to set remote header and to send PROXY protocol preamble, required by both"
+    )]
+    trusted_proxies: Vec<IpNet>,
+
+    #[clap(
+        long = "no-remote-policy",
+        default_value = "reject",
+        help = "when remote addr can't be determined: reject, fall back to peer or continue as unknown"
+    )]
+    no_remote_policy: NoRemotePolicy,
+
+    #[clap(
+        long = "ip-privacy",
+        default_value = "off",
+        help = "client ip in logs, audit & in-flight listing: off, truncate to /24 & /48 or keyed hash"
+    )]
+    ip_privacy: IpPrivacyMode,
+
+    #[clap(long = "ip-privacy-key", help = "key of ip privacy hash mode")]
+    ip_privacy_key: Option<String>,
+
+    #[clap(
+        long = "access-id-header",
+        help = "trusted inbound access id header, X-Request-Id like uuid header or traceparent"
+    )]
+    access_id_headers: Vec<AccessIdSource>,
+
+    #[clap(
+        long = "access-id-response-header",
+        default_value = "X-Access-Id",
+        help = "response header carrying access id"
+    )]
+    access_id_response_header: String,
+
+    #[clap(
+        long = "slow-request-ms",
+        help = "global request latency budget in millis"
+    )]
+    slow_request_ms: Option<u64>,
+
+    #[clap(
+        long = "slow-route",
+        help = "per route latency budget, {route}={millis}, eg. /users/{id}=500"
+    )]
+    slow_routes: Vec<RouteBudget>,
+
+    #[clap(
+        long = "slow-request-mid-flight",
+        help = "warn once while request still running past its budget"
+    )]
+    slow_request_mid_flight: bool,
+
+    #[clap(
+        long = "body-capture",
+        value_enum,
+        default_value = "off",
+        help = "access log body capture mode"
+    )]
+    body_capture: BodyCaptureMode,
+
+    #[clap(
+        long = "body-capture-sample-percent",
+        default_value = "1",
+        help = "sampled percent of requests in sampled body capture mode"
+    )]
+    body_capture_sample_percent: f64,
+
+    #[clap(
+        long = "body-capture-max-bytes",
+        default_value = "4096",
+        help = "max captured bytes per body"
+    )]
+    body_capture_max_bytes: usize,
+
+    #[clap(
+        long = "body-redact-key",
+        default_values = ["password", "token"],
+        help = "json/form key redacted in captured bodies, case insensitive"
+    )]
+    body_redact_keys: Vec<String>,
+
+    #[clap(
+        long = "log-request-headers",
+        help = "dump request headers on request begin, always on in debug build"
+    )]
+    log_request_headers: bool,
+
+    #[clap(
+        long = "header-log-allow",
+        help = "request header to dump, all headers when not set"
+    )]
+    header_log_allow: Vec<String>,
+
+    #[clap(
+        long = "header-log-deny",
+        default_values = DEFAULT_DENIED_HEADERS,
+        help = "header never dumped in plain text"
+    )]
+    header_log_deny: Vec<String>,
+
+    #[clap(
+        long = "header-log-hash-denied",
+        help = "dump denied headers as sha256 prefix instead of dropping them"
+    )]
+    header_log_hash_denied: bool,
+
+    #[clap(
+        long = "log-response-header",
+        help = "response header logged on request end"
+    )]
+    log_response_headers: Vec<String>,
+
+    #[clap(
+        long = "access-rules",
+        help = "access log rule json file, eg. [{\"route\": \"/gen_204\", \"action\": \"skip\"}]"
+    )]
+    access_rules: Option<String>,
+
+    #[clap(
+        long = "access-rules-reload-secs",
+        default_value = "10",
+        help = "access log rule file reload check interval"
+    )]
+    access_rules_reload_secs: u64,
+
+    #[clap(
+        long = "client-app",
+        help = "app name recognized in user agent as {app}/{version}, eg. HostApp"
+    )]
+    client_apps: Vec<String>,
+
+    #[clap(
+        long = "min-app-version",
+        help = "per route minimum app version, {route}={app}/{version}, eg. /feed=HostApp/2.1.0, enforced only by handlers extracting ClientInfo"
+    )]
+    min_app_versions: Vec<MinAppVersion>,
+
+    #[clap(
+        long = "geoip-db",
+        help = "maxmind .mmdb database for remote geo, eg. country/city and asn databases"
+    )]
+    geoip_dbs: Vec<String>,
+
+    #[clap(
+        long = "geoip-reload-secs",
+        default_value = "60",
+        help = "geoip database file replacement check interval"
+    )]
+    geoip_reload_secs: u64,
+
+    #[clap(
+        long = "max-connections-per-ip",
+        help = "simultaneous connections per client ip, more are answered 429 & closed on accept, trusted proxies are exempt"
+    )]
+    max_connections_per_ip: Option<usize>,
+
+    #[clap(
+        long = "max-requests-per-ip",
+        help = "concurrent in-flight requests per remote addr"
+    )]
+    max_requests_per_ip: Option<usize>,
+
+    #[clap(
+        long = "client-limit-retry-after-secs",
+        default_value = "1",
+        help = "Retry-After of requests rejected by client limits"
+    )]
+    client_limit_retry_after_secs: u64,
+
+    #[clap(
+        long = "ip-filter",
+        help = "allow/deny cidr lists of every route, json file or redis://host/db#key, eg. {\"deny\": [\"192.0.2.0/24\"]}"
+    )]
+    ip_filter: Option<String>,
+
+    #[clap(
+        long = "admin-ip-filter",
+        help = "allow/deny cidr lists of admin routes & /metrics, json file or redis://host/db#key, also serves them on api bind, neither set leaves /metrics unserved"
+    )]
+    admin_ip_filter: Option<String>,
+
+    #[clap(
+        long = "ip-filter-reload-secs",
+        default_value = "10",
+        help = "ip filter reload check interval"
+    )]
+    ip_filter_reload_secs: u64,
+
+    #[clap(
+        long = "access-audit-db",
+        help = "postgres url of access audit table, audit disabled when not set"
+    )]
+    access_audit_db: Option<String>,
+
+    #[clap(
+        long = "access-audit-pool-size",
+        default_value = "2",
+        help = "access audit database pool size"
+    )]
+    access_audit_pool_size: u32,
+
+    #[clap(
+        long = "access-audit-queue",
+        default_value = "8192",
+        help = "access audit records queued before dropping"
+    )]
+    access_audit_queue: usize,
+
+    #[clap(
+        long = "access-audit-batch",
+        default_value = "256",
+        help = "access audit records per insert"
+    )]
+    access_audit_batch: usize,
+
+    #[clap(
+        long = "access-audit-flush-ms",
+        default_value = "1000",
+        help = "access audit flush interval in millis"
+    )]
+    access_audit_flush_ms: u64,
+
+    #[clap(
+        long = "access-audit-retention-days",
+        default_value = "90",
+        help = "access audit records older than this are purged"
+    )]
+    access_audit_retention_days: u64,
+
     #[clap(long = "log-dir", default_value = "logs", help = "log output dir")]
     log_dir: String,
 
@@ -41,27 +322,239 @@ struct Opts {
 
     #[clap(long = "log-loki", help = "log loki push endpoint")]
     log_loki: Option<String>,
+
+    #[clap(
+        long = "access-log",
+        help = "write one line per finished request to access.*.log in log dir, access rules don't apply"
+    )]
+    access_log: Option<AccessLogFormat>,
+
+    #[clap(
+        long = "access-log-rotation",
+        default_value = "daily",
+        help = "access log file rotation"
+    )]
+    access_log_rotation: AccessLogRotation,
+
+    #[clap(
+        long = "access-log-max-files",
+        default_value = "30",
+        help = "access log files kept"
+    )]
+    access_log_max_files: usize,
+}
+
+fn parse_header_names(names: &[String]) -> Result<Vec<HeaderName>> {
+    names
+        .iter()
+        .map(|v| HeaderName::from_str(v).with_context(|| format!("invalid header name {}", v)))
+        .collect()
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let Opts {
         bind,
+        admin_bind,
+        proxy_protocol,
+        proxy_protocol_timeout_ms,
         remote_header,
+        trusted_proxies,
+        no_remote_policy,
+        ip_privacy,
+        ip_privacy_key,
+        access_id_headers,
+        access_id_response_header,
+        slow_request_ms,
+        slow_routes,
+        slow_request_mid_flight,
+        body_capture,
+        body_capture_sample_percent,
+        body_capture_max_bytes,
+        body_redact_keys,
+        log_request_headers,
+        header_log_allow,
+        header_log_deny,
+        header_log_hash_denied,
+        log_response_headers,
+        access_rules,
+        access_rules_reload_secs,
+        client_apps,
+        min_app_versions,
+        geoip_dbs,
+        geoip_reload_secs,
+        max_connections_per_ip,
+        max_requests_per_ip,
+        client_limit_retry_after_secs,
+        ip_filter,
+        admin_ip_filter,
+        ip_filter_reload_secs,
+        access_audit_db,
+        access_audit_pool_size,
+        access_audit_queue,
+        access_audit_batch,
+        access_audit_flush_ms,
+        access_audit_retention_days,
         log_dir,
         log_filter,
         log_loki,
+        access_log,
+        access_log_rotation,
+        access_log_max_files,
     } = Opts::parse();
 
-    let (_tracing_file_guard, tracing_loki_guard) =
-        tracing_output::setup(&log_dir, &log_filter, log_loki.as_deref())
+    let access_sink = access_log.map(|format| AccessSinkOptions {
+        format,
+        rotation: access_log_rotation,
+        max_files: access_log_max_files,
+    });
+    let (_tracing_file_guards, tracing_loki_guard) =
+        tracing_output::setup(&log_dir, &log_filter, log_loki.as_deref(), access_sink)
             .context("setup tracing")?;
 
-    let state = HostState::new(remote_header);
-    let router = Router::new()
+    let access_id_response_header = HeaderName::from_str(&access_id_response_header)
+        .context("parse access id response header")?;
+
+    let slow_request = SlowRequestPolicy::new(
+        slow_request_ms.map(Duration::from_millis),
+        slow_routes,
+        slow_request_mid_flight,
+    );
+    let body_capture = BodyCapturePolicy::new(
+        body_capture,
+        body_capture_sample_percent,
+        body_capture_max_bytes,
+        body_redact_keys,
+    );
+    let ip_privacy =
+        IpPrivacy::new(ip_privacy, ip_privacy_key.as_deref()).context("create ip privacy")?;
+    let mut header_log_deny =
+        parse_header_names(&header_log_deny).context("parse header log deny list")?;
+    if ip_privacy.enabled() {
+        let ip_headers = IP_HEADERS
+            .iter()
+            .map(|v| v.to_string())
+            .chain(remote_header.clone())
+            .collect::<Vec<_>>();
+        header_log_deny.extend(parse_header_names(&ip_headers).context("parse ip headers")?);
+    }
+    let header_log = HeaderLogPolicy::new(
+        log_request_headers || cfg!(debug_assertions),
+        parse_header_names(&header_log_allow).context("parse header log allow list")?,
+        header_log_deny,
+        header_log_hash_denied,
+        parse_header_names(&log_response_headers).context("parse logged response headers")?,
+    );
+    let access_rules =
+        Arc::new(AccessRules::load(access_rules.as_deref()).context("load access rules")?);
+    access_rules
+        .clone()
+        .spawn_reload(Duration::from_secs(access_rules_reload_secs));
+
+    let geoip = Arc::new(GeoIp::load(&geoip_dbs).context("load geoip databases")?);
+    geoip
+        .clone()
+        .spawn_reload(Duration::from_secs(geoip_reload_secs));
+
+    let (access_audit, access_audit_worker) = match access_audit_db {
+        Some(db) => {
+            let pool = database_init::create(&db, access_audit_pool_size)
+                .await
+                .context("create access audit database pool")?;
+            database_init::check(&pool)
+                .await
+                .context("check access audit database")?;
+            let (audit, worker) = AccessAudit::spawn(
+                pool,
+                AccessAuditOptions {
+                    queue: access_audit_queue,
+                    batch: access_audit_batch,
+                    flush_every: Duration::from_millis(access_audit_flush_ms),
+                    retention: Duration::from_secs(access_audit_retention_days * 24 * 60 * 60),
+                    purge_every: Duration::from_secs(60 * 60),
+                },
+            );
+            (Some(audit), Some(worker))
+        }
+        None => (None, None),
+    };
+
+    let ip_filter = Arc::new(
+        IpFilter::load("global", ip_filter.as_deref())
+            .await
+            .context("load ip filter")?,
+    );
+    ip_filter
+        .clone()
+        .spawn_reload(Duration::from_secs(ip_filter_reload_secs));
+    let admin_on_api = admin_bind.is_none() && admin_ip_filter.is_some();
+    let admin_ip_filter = Arc::new(
+        IpFilter::load("admin", admin_ip_filter.as_deref())
+            .await
+            .context("load admin ip filter")?,
+    );
+    admin_ip_filter
+        .clone()
+        .spawn_reload(Duration::from_secs(ip_filter_reload_secs));
+
+    // a preamble sets client addr, only proxies may send one
+    if proxy_protocol != ProxyProtocolMode::Off && trusted_proxies.is_empty() {
+        return Err(anyhow!("--proxy-protocol requires --trusted-proxy"));
+    }
+    // so does remote header, any client could spoof it otherwise
+    if remote_header.is_some() && trusted_proxies.is_empty() {
+        return Err(anyhow!("--remote-header requires --trusted-proxy"));
+    }
+    let proxy_protocol_trusted = trusted_proxies.clone();
+    let connection_limit = ConnectionLimit::new(
+        max_connections_per_ip,
+        trusted_proxies.clone(),
+        Duration::from_secs(client_limit_retry_after_secs),
+    );
+    let inflight = InFlightRegistry::new(ip_privacy.clone());
+    let state = HostState::new(
+        RemoteAddrConfig {
+            header: remote_header,
+            trusted_proxies,
+            no_remote_policy,
+            privacy: ip_privacy.clone(),
+        },
+        AccessLogConfig {
+            access_id_sources: access_id_headers,
+            access_id_response_header,
+            slow_request,
+            body_capture,
+            header_log,
+            access_rules,
+            geoip,
+            client_info: ClientInfoPolicy::new(client_apps, min_app_versions),
+            access_audit,
+            inflight: inflight.clone(),
+        },
+    );
+    let admin = Router::new()
+        .route("/metrics", get(metrics::serve))
+        .route("/admin/inflight", get(inflight::list))
+        .route("/admin/inflight/{access_id}", delete(inflight::cancel))
+        .route_layer(IpFilterLayer::new(state.clone(), admin_ip_filter));
+    // admin routes list & cancel any request, never open to api clients by default
+    let mut router = Router::new().route("/gen_204", get(|| async { StatusCode::NO_CONTENT }));
+    if admin_on_api {
+        router = router.merge(admin.clone());
+    } else if admin_bind.is_none() {
+        warn!("admin routes & /metrics not served, set --admin-bind or --admin-ip-filter");
+    }
+    let router = router
+        .layer(RequestLimitLayer::new(
+            state.clone(),
+            RequestLimitConfig {
+                max_per_ip: max_requests_per_ip,
+                retry_after: Duration::from_secs(client_limit_retry_after_secs),
+            },
+        ))
+        .layer(IpFilterLayer::new(state.clone(), ip_filter))
         .layer(AccessLog::new(state.clone()))
-        .with_state(state)
-        .route("/gen_204", get(|| async { StatusCode::NO_CONTENT }));
+        .with_state(state.clone());
 
     // bind tcp socket
     let tcp_listener = TcpListener::bind(bind)
@@ -69,20 +562,78 @@ async fn main() -> Result<()> {
         .with_context(|| format!("can't bind tcp socket at {}", bind))?;
     let tcp_bind = tcp_listener.local_addr().context("get tcp bound addr")?;
     info!("bound at {}", tcp_bind);
+    let listener = ProxyListener::new(
+        tcp_listener,
+        proxy_protocol,
+        proxy_protocol_trusted,
+        Duration::from_millis(proxy_protocol_timeout_ms),
+        ip_privacy.clone(),
+        connection_limit,
+    );
+
+    let admin_server = match admin_bind {
+        Some(admin_bind) => {
+            let tcp_listener = TcpListener::bind(admin_bind)
+                .await
+                .with_context(|| format!("can't bind admin tcp socket at {}", admin_bind))?;
+            info!("admin bound at {}", admin_bind);
+            let listener = ProxyListener::new(
+                tcp_listener,
+                ProxyProtocolMode::Off,
+                Vec::new(),
+                Duration::from_millis(proxy_protocol_timeout_ms),
+                ip_privacy,
+                ConnectionLimit::default(),
+            );
+            let router = admin.layer(AccessLog::new(state.clone())).with_state(state);
+            Some(tokio::spawn(
+                async move {
+                    axum::serve(
+                        listener,
+                        router.into_make_service_with_connect_info::<ConnectionInfo>(),
+                    )
+                    .with_graceful_shutdown(quit_sig::wait())
+                    .await
+                }
+                .instrument(info_span!("admin")),
+            ))
+        }
+        None => None,
+    };
 
     // enter task loop
     info!("host start up");
     axum::serve(
-        tcp_listener,
-        router.into_make_service_with_connect_info::<SocketAddr>(),
+        listener,
+        router.into_make_service_with_connect_info::<ConnectionInfo>(),
     )
-    .with_graceful_shutdown(quit_sig::wait().instrument(info_span!("wait-quit-sig")))
+    .with_graceful_shutdown({
+        let inflight = inflight.clone();
+        async move {
+            quit_sig::wait().await;
+            inflight.report();
+        }
+        .instrument(info_span!("wait-quit-sig"))
+    })
     .await
     .context("run http")?;
 
+    if let Some(admin_server) = admin_server {
+        admin_server
+            .await
+            .context("join admin http")?
+            .context("run admin http")?;
+    }
+
     // clean
     info!("host shutdown");
 
+    if let Some(worker) = access_audit_worker {
+        info!("shutdown access audit");
+        worker.shutdown().await;
+        info!("shutdown access audit ok");
+    }
+
     if let Some(guard) = tracing_loki_guard {
         info!("shutdown loki");
         guard.shutdown().await;
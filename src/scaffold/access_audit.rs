@@ -0,0 +1,209 @@
+use std::{
+    ops::DerefMut,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use diesel::{ExpressionMethods, Insertable, QueryDsl};
+use diesel_async::RunQueryDsl;
+use tokio::{
+    select, spawn,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{MissedTickBehavior, interval},
+};
+use tracing::{Instrument, error, info, info_span, warn};
+use uuid::Uuid;
+
+use crate::{
+    scaffold::{database_init::DbPool, metrics, pretty::Pretty},
+    schema::access_audit,
+};
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = access_audit)]
+pub struct AccessRecord {
+    pub access_id: Uuid,
+    pub remote_ip: String,
+    pub remote_port: Option<i32>,
+    pub method: String,
+    pub route: String,
+    pub status: i16,
+    pub cost_ms: i64,
+    pub user_id: Option<String>,
+    pub finished_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AccessAuditOptions {
+    pub queue: usize,
+    pub batch: usize,
+    pub flush_every: Duration,
+    pub retention: Duration,
+    pub purge_every: Duration,
+}
+
+/// finished request records, written to postgres in batches
+///
+/// records are dropped rather than slowing requests down when queue is full
+#[derive(Clone)]
+pub struct AccessAudit {
+    sender: mpsc::Sender<AccessRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessAudit {
+    pub fn spawn(pool: DbPool, options: AccessAuditOptions) -> (Self, AccessAuditWorker) {
+        let (sender, receiver) = mpsc::channel(options.queue.max(1));
+        let (quit, quit_receiver) = oneshot::channel();
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let writer = spawn(
+            write_loop(
+                pool.clone(),
+                options,
+                receiver,
+                quit_receiver,
+                dropped.clone(),
+            )
+            .instrument(info_span!("access-audit-write")),
+        );
+        let purge = spawn(purge_loop(pool, options).instrument(info_span!("access-audit-purge")));
+
+        (
+            Self { sender, dropped },
+            AccessAuditWorker {
+                quit,
+                writer,
+                purge,
+            },
+        )
+    }
+
+    pub fn record(&self, record: AccessRecord) {
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                metrics::record_access_audit("dropped", 1);
+                // reported by writer, logging here would flood while backlogged
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                metrics::record_access_audit("dropped", 1);
+            }
+        }
+    }
+}
+
+pub struct AccessAuditWorker {
+    quit: oneshot::Sender<()>,
+    writer: JoinHandle<()>,
+    purge: JoinHandle<()>,
+}
+
+impl AccessAuditWorker {
+    /// flush queued records & stop
+    pub async fn shutdown(self) {
+        self.purge.abort();
+        let _ = self.quit.send(());
+        if let Err(err) = self.writer.await {
+            error!(err=?Pretty(err), "access audit writer panicked");
+        }
+    }
+}
+
+async fn write_loop(
+    pool: DbPool,
+    options: AccessAuditOptions,
+    mut receiver: mpsc::Receiver<AccessRecord>,
+    mut quit: oneshot::Receiver<()>,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut ticker = interval(options.flush_every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let batch_size = options.batch.max(1);
+    let mut batch = Vec::with_capacity(batch_size);
+    loop {
+        let remain = batch_size - batch.len();
+        select! {
+            _ = &mut quit => break,
+            _ = ticker.tick() => {}
+            received = receiver.recv_many(&mut batch, remain) => {
+                if received == 0 {
+                    break;
+                }
+                if batch.len() < batch_size {
+                    continue;
+                }
+            }
+        }
+
+        let dropped = dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(dropped, "access audit queue full, records dropped");
+        }
+        flush(&pool, &mut batch).await;
+    }
+
+    receiver.close();
+    while let Some(record) = receiver.recv().await {
+        batch.push(record);
+        if batch.len() >= batch_size {
+            flush(&pool, &mut batch).await;
+        }
+    }
+    flush(&pool, &mut batch).await;
+    info!("access audit writer stopped");
+}
+
+async fn flush(pool: &DbPool, batch: &mut Vec<AccessRecord>) {
+    if batch.is_empty() {
+        return;
+    }
+    let count = batch.len();
+    match insert(pool, batch).await {
+        Ok(written) => metrics::record_access_audit("written", written),
+        Err(err) => {
+            metrics::record_access_audit("failed", count);
+            error!(err=?Pretty(err), count, "write access audit records error");
+        }
+    }
+    batch.clear();
+}
+
+/// rows inserted
+async fn insert(pool: &DbPool, batch: &[AccessRecord]) -> Result<usize> {
+    let mut conn = pool.get().await.context("connect to database")?;
+    let written = diesel::insert_into(access_audit::table)
+        .values(batch)
+        .execute(conn.deref_mut())
+        .await
+        .context("insert access audit records")?;
+    Ok(written)
+}
+
+async fn purge_loop(pool: DbPool, options: AccessAuditOptions) {
+    let mut ticker = interval(options.purge_every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match purge(&pool, options.retention).await {
+            Ok(purged) => info!(purged, "access audit records purged"),
+            Err(err) => error!(err=?Pretty(err), "purge access audit records error"),
+        }
+    }
+}
+
+async fn purge(pool: &DbPool, retention: Duration) -> Result<usize> {
+    let retention = chrono::Duration::from_std(retention).context("convert retention")?;
+    let mut conn = pool.get().await.context("connect to database")?;
+    diesel::delete(access_audit::table.filter(access_audit::finished_at.lt(Utc::now() - retention)))
+        .execute(conn.deref_mut())
+        .await
+        .context("delete expired access audit records")
+}
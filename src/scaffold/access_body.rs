@@ -0,0 +1,193 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+};
+
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use pin_project::{pin_project, pinned_drop};
+
+/// bounded copy of body bytes passing through `AccessBody`
+#[derive(Clone)]
+pub struct BodyCapture {
+    inner: Arc<Mutex<CaptureBuffer>>,
+}
+
+struct CaptureBuffer {
+    data: Vec<u8>,
+    limit: usize,
+    total: u64,
+    complete: bool,
+}
+
+pub struct CapturedBody {
+    pub data: Vec<u8>,
+    /// bytes seen, including those beyond capture limit
+    pub total: u64,
+    pub truncated: bool,
+    /// body reached end of stream
+    pub complete: bool,
+}
+
+impl BodyCapture {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(CaptureBuffer {
+                data: Vec::new(),
+                limit,
+                total: 0,
+                complete: false,
+            })),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut CaptureBuffer) -> R) -> R {
+        let mut guard = self.inner.lock().unwrap_or_else(|err| err.into_inner());
+        f(&mut guard)
+    }
+
+    fn push(&self, data: &[u8]) {
+        self.with(|buffer| {
+            buffer.total += data.len() as u64;
+            let remain = buffer.limit.saturating_sub(buffer.data.len());
+            buffer
+                .data
+                .extend_from_slice(&data[..remain.min(data.len())]);
+        })
+    }
+
+    fn complete(&self) {
+        self.with(|buffer| buffer.complete = true)
+    }
+
+    pub fn snapshot(&self) -> CapturedBody {
+        self.with(|buffer| CapturedBody {
+            data: buffer.data.clone(),
+            total: buffer.total,
+            truncated: buffer.total > buffer.data.len() as u64,
+            complete: buffer.complete,
+        })
+    }
+}
+
+/// data bytes passed through `AccessBody`
+#[derive(Clone, Default)]
+pub struct ByteCount(Arc<AtomicU64>);
+
+impl ByteCount {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, len: usize) {
+        self.0.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+/// called with whether body reached end of stream
+pub type OnFinish = Box<dyn FnOnce(bool) + Send>;
+
+/// body wrapper used by access log to observe request & response bodies
+///
+/// `on_finish` runs once, at end of stream or when body is dropped
+#[pin_project(PinnedDrop)]
+pub struct AccessBody<B> {
+    #[pin]
+    inner: B,
+    count: ByteCount,
+    capture: Option<BodyCapture>,
+    on_finish: Option<OnFinish>,
+    complete: bool,
+}
+
+impl<B> AccessBody<B>
+where
+    B: Body,
+{
+    pub fn new(
+        inner: B,
+        count: ByteCount,
+        capture: Option<BodyCapture>,
+        on_finish: Option<OnFinish>,
+    ) -> Self {
+        // empty bodies may never be polled
+        let complete = inner.is_end_stream();
+        if complete && let Some(capture) = capture.as_ref() {
+            capture.complete();
+        }
+        Self {
+            inner,
+            count,
+            capture,
+            on_finish,
+            complete,
+        }
+    }
+}
+
+impl<B> Body for AccessBody<B>
+where
+    B: Body<Data = Bytes>,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let mut inner = this.inner;
+        let frame = ready!(inner.as_mut().poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => {
+                if let Some(data) = frame.data_ref() {
+                    this.count.add(data.len());
+                    if let Some(capture) = this.capture.as_ref() {
+                        capture.push(data);
+                    }
+                }
+                // hyper stops polling once body reports end of stream
+                if inner.is_end_stream() {
+                    *this.complete = true;
+                    if let Some(capture) = this.capture.as_ref() {
+                        capture.complete();
+                    }
+                }
+            }
+            Some(Err(_)) => {}
+            None => {
+                *this.complete = true;
+                if let Some(capture) = this.capture.as_ref() {
+                    capture.complete();
+                }
+                if let Some(on_finish) = this.on_finish.take() {
+                    on_finish(true);
+                }
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for AccessBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if let Some(on_finish) = this.on_finish.take() {
+            on_finish(*this.complete);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use std::str::FromStr;
+
+use anyhow::{Error, Result, anyhow};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use tracing::debug;
+use uuid::Uuid;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+
+/// trusted inbound header which may carry the access id
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum AccessIdSource {
+    /// header value is an uuid, eg. `X-Request-Id`
+    RequestId(HeaderName),
+    /// W3C `traceparent`, trace id is used as access id
+    TraceParent,
+}
+
+impl FromStr for AccessIdSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case(TRACEPARENT) {
+            return Ok(Self::TraceParent);
+        }
+        HeaderName::from_str(s)
+            .map(Self::RequestId)
+            .map_err(|err| anyhow!("invalid access id header {:?}: {}", s, err))
+    }
+}
+
+impl TryFrom<String> for AccessIdSource {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub parent_span_id: String,
+    pub flags: u8,
+    pub state: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InboundAccessId {
+    pub id: Uuid,
+    pub source: &'static str,
+    pub trace: Option<TraceContext>,
+}
+
+impl InboundAccessId {
+    /// check trusted headers in order, first valid one wins
+    pub fn extract(headers: &HeaderMap<HeaderValue>, sources: &[AccessIdSource]) -> Option<Self> {
+        for source in sources {
+            let found = match source {
+                AccessIdSource::RequestId(name) => headers
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| Uuid::parse_str(v.trim()).ok())
+                    .filter(|v| !v.is_nil())
+                    .map(|id| Self {
+                        id,
+                        source: "request id",
+                        trace: None,
+                    }),
+                AccessIdSource::TraceParent => headers
+                    .get(TRACEPARENT)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(parse_traceparent)
+                    .map(|(id, mut trace)| {
+                        trace.state = headers
+                            .get(TRACESTATE)
+                            .and_then(|v| v.to_str().ok())
+                            .map(|v| v.to_string());
+                        Self {
+                            id,
+                            source: "traceparent",
+                            trace: Some(trace),
+                        }
+                    }),
+            };
+
+            match found {
+                Some(v) => return Some(v),
+                None => debug!(?source, "no valid inbound access id"),
+            }
+        }
+        None
+    }
+}
+
+/// parse `{version}-{trace-id}-{parent-id}-{flags}`
+fn parse_traceparent(value: &str) -> Option<(Uuid, TraceContext)> {
+    fn is_lower_hex(s: &str, len: usize) -> bool {
+        s.len() == len && s.bytes().all(|v| matches!(v, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_span_id = parts.next()?;
+    let flags = parts.next()?;
+
+    // version 00 has exactly four parts, future versions may append more
+    if !is_lower_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+    if !is_lower_hex(trace_id, 32) || !is_lower_hex(parent_span_id, 16) || !is_lower_hex(flags, 2) {
+        return None;
+    }
+
+    let id = u128::from_str_radix(trace_id, 16).ok()?;
+    let parent = u64::from_str_radix(parent_span_id, 16).ok()?;
+    if id == 0 || parent == 0 {
+        return None;
+    }
+
+    Some((
+        Uuid::from_u128(id),
+        TraceContext {
+            trace_id: trace_id.to_string(),
+            parent_span_id: parent_span_id.to_string(),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            state: None,
+        },
+    ))
+}
@@ -1,30 +1,67 @@
 use std::{
     fmt::Debug,
     future::Future,
+    net::SocketAddr,
     pin::Pin,
-    task::{Context, Poll, ready},
-    time::Instant,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use axum::{
-    extract::Request,
+    BoxError,
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Request},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version, header},
     response::{IntoResponse, Response},
 };
+use bytes::Bytes;
+use chrono::Utc;
+use http_body::Body as HttpBody;
 use pin_project::{pin_project, pinned_drop};
+use tokio::{sync::oneshot, time::Sleep};
 use tower::Service;
 use tower_layer::Layer;
-use tracing::{Level, Span, debug, error, info, span, warn};
+use tracing::{Level, Span, debug, error, field::Empty, info, span, warn};
 use uuid::Uuid;
 
 use crate::{
     api::state::HostState,
     scaffold::{
+        access_audit::{AccessAudit, AccessRecord},
+        access_body::{AccessBody, BodyCapture, ByteCount},
+        access_id::{AccessIdSource, InboundAccessId},
+        access_rules::{AccessRules, AccessVerbosity},
+        access_sink::ACCESS_TARGET,
+        body_capture::BodyCapturePolicy,
+        client_info::ClientInfoPolicy,
+        connection_info::ConnectionInfo,
+        geoip::GeoIp,
+        header_log::HeaderLogPolicy,
+        inflight::{InFlightGuard, InFlightRegistry},
+        metrics::{self, RequestEndType},
         pretty::PrettyOpt,
-        remote_addr::RemoteAddr,
+        principal::PrincipalSlot,
+        remote_addr::{NoRemotePolicy, RemoteAddr},
         rest::{RestResponse, RestStatus},
+        slow_request::SlowRequestPolicy,
     },
 };
 
+/// `info!` or `debug!` by request verbosity, nothing when off
+macro_rules! access_event {
+    ($verbosity:expr, $($arg:tt)+) => {
+        match $verbosity {
+            AccessVerbosity::Info => info!($($arg)+),
+            AccessVerbosity::Debug => debug!($($arg)+),
+            AccessVerbosity::Off => {}
+        }
+    };
+}
+
+/// route label used when no route matched, eg. fallback
+pub const UNMATCHED_ROUTE: &str = "<unmatched>";
+
 #[derive(Copy, Clone)]
 pub struct AccessLogId(pub Uuid);
 
@@ -40,6 +77,62 @@ impl AccessLogId {
     }
 }
 
+/// access id echoed in response header
+#[derive(Clone)]
+pub struct AccessIdHeader {
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl AccessIdHeader {
+    fn new(name: HeaderName, access_id: Uuid) -> Self {
+        let value = HeaderValue::from_str(&access_id.to_string())
+            .expect("uuid is always a valid header value");
+        Self { name, value }
+    }
+
+    fn apply<B>(&self, response: &mut Response<B>) {
+        response
+            .headers_mut()
+            .insert(self.name.clone(), self.value.clone());
+    }
+}
+
+/// access log settings, kept in `HostState`
+pub struct AccessLogConfig {
+    pub access_id_sources: Vec<AccessIdSource>,
+    pub access_id_response_header: HeaderName,
+    pub slow_request: SlowRequestPolicy,
+    pub body_capture: BodyCapturePolicy,
+    pub header_log: HeaderLogPolicy,
+    pub access_rules: Arc<AccessRules>,
+    pub geoip: Arc<GeoIp>,
+    pub client_info: ClientInfoPolicy,
+    pub access_audit: Option<AccessAudit>,
+    pub inflight: InFlightRegistry,
+}
+
+#[cfg(test)]
+impl AccessLogConfig {
+    /// `Opts` defaults without audit, trusting `X-Request-Id`
+    pub fn for_test() -> Self {
+        Self {
+            access_id_sources: vec![AccessIdSource::RequestId(HeaderName::from_static(
+                "x-request-id",
+            ))],
+            access_id_response_header: HeaderName::from_static("x-access-id"),
+            slow_request: SlowRequestPolicy::default(),
+            body_capture: BodyCapturePolicy::new(Default::default(), 0.0, 0, Vec::new()),
+            header_log: HeaderLogPolicy::new(false, Vec::new(), Vec::new(), false, Vec::new()),
+            access_rules: Default::default(),
+            geoip: Default::default(),
+            client_info: ClientInfoPolicy::new(Vec::new(), Vec::new()),
+            access_audit: None,
+            inflight: InFlightRegistry::new(Default::default()),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AccessLog {
     state: HostState,
@@ -68,123 +161,436 @@ pub struct AccessLogService<S> {
     state: HostState,
 }
 
-impl<S, Req, Resp> Service<Request<Req>> for AccessLogService<S>
+impl<S, Resp> Service<Request> for AccessLogService<S>
 where
-    S: Service<Request<Req>, Response = Response<Resp>>,
+    S: Service<Request, Response = Response<Resp>>,
     S::Future: Future<Output = Result<Response<Resp>, S::Error>>,
     S::Error: Debug,
+    Resp: HttpBody<Data = Bytes> + Send + 'static,
+    Resp::Error: Into<BoxError>,
 {
-    type Response = AccessLogServiceBody<Resp>;
+    type Response = Response;
     type Error = S::Error;
-    type Future = AccessLogServiceOptFuture<S::Future>;
+    type Future = AccessLogServiceFuture<S::Future>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, mut req: Request<Req>) -> Self::Future {
-        let id = Uuid::new_v4();
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let inbound = InboundAccessId::extract(req.headers(), self.state.access_id_sources());
+        let id = match &inbound {
+            Some(v) => v.id,
+            None => Uuid::new_v4(),
+        };
         req.extensions_mut().insert(AccessLogId(id));
+        let id_header = AccessIdHeader::new(self.state.access_id_response_header().clone(), id);
+
+        // route template keeps log & metric cardinality bounded, raw path is kept aside
+        let route = match req.extensions().get::<MatchedPath>() {
+            Some(v) => v.as_str().to_string(),
+            None => UNMATCHED_ROUTE.to_string(),
+        };
+
+        let verbosity = self.state.access_rules().verbosity(req.method(), &route);
 
-        let span = span!(Level::INFO, "request", access_id=%id);
-        let req = {
+        let span = span!(
+            Level::INFO,
+            "request",
+            access_id = %id,
+            route = %route,
+            trace_id = Empty,
+            parent_span_id = Empty,
+            user_id = Empty,
+            tenant_id = Empty,
+            auth_method = Empty,
+        );
+        let trace = inbound.as_ref().and_then(|v| v.trace.as_ref());
+        if let Some(trace) = trace {
+            span.record("trace_id", trace.trace_id.as_str());
+            span.record("parent_span_id", trace.parent_span_id.as_str());
+        }
+        let principal = PrincipalSlot::new(span.clone());
+        req.extensions_mut().insert(principal.clone());
+        let (req, remote) = {
             let _guard = span.enter();
             let (mut parts, body) = req.into_parts();
+            let connection = parts
+                .extensions
+                .get::<ConnectInfo<ConnectionInfo>>()
+                .map(|v| v.0.clone());
+            // existing `ConnectInfo<SocketAddr>` extractors keep working, without mapped ipv6
+            if let Some(connection) = &connection {
+                let peer =
+                    SocketAddr::new(connection.peer.ip().to_canonical(), connection.peer.port());
+                parts.extensions.insert(ConnectInfo(peer));
+            }
+            let connection_requests = connection.as_ref().map(ConnectionInfo::next_request);
             let remote = match RemoteAddr::parse(
                 &mut parts.extensions,
                 &parts.headers,
-                self.state.remote_header(),
+                self.state.remote_addr(),
             ) {
-                Ok(v) => v,
+                Ok(v) => Some(v),
                 Err(err) => {
-                    info!(%err, "extract remote error");
-                    return AccessLogServiceOptFuture::NoRemoteAddr { access_id: id };
+                    let policy = self.state.remote_addr().no_remote_policy;
+                    let fallback = match policy {
+                        NoRemotePolicy::Peer => RemoteAddr::peer(&parts.extensions),
+                        NoRemotePolicy::Reject | NoRemotePolicy::Unknown => None,
+                    };
+                    info!(%err, policy = policy.name(), fallback = fallback.is_some(), "extract remote error");
+                    if let Some(fallback) = fallback {
+                        fallback.save_extension(&mut parts.extensions);
+                    }
+                    fallback
                 }
             };
-            info!(
+            let geo = remote
+                .filter(|_| self.state.geoip().enabled())
+                .map(|v| self.state.geoip().lookup(v.ip));
+            if let Some(geo) = geo.clone() {
+                parts.extensions.insert(geo);
+            }
+            let client = self.state.client_info().parse(
+                parts
+                    .headers
+                    .get(header::USER_AGENT)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default(),
+            );
+            parts.extensions.insert(client.clone());
+            access_event!(
+                verbosity,
                 target: "request",
                 request_phase = "begin",
-                remote_ip = %remote.ip,
-                remote_port = %PrettyOpt(remote.port),
+                remote_ip = remote.map(|v| display(self.state.remote_addr().privacy.mask(v.ip))),
+                remote_port = remote.map(|v| display(PrettyOpt(v.port))),
+                remote_country = geo.as_ref().and_then(|v| v.country.as_deref()),
+                remote_region = geo.as_ref().and_then(|v| v.region.as_deref()),
+                remote_asn = geo.as_ref().and_then(|v| v.asn),
+                remote_as_org = geo.as_ref().and_then(|v| v.as_org.as_deref()),
+                client_browser = client.browser.as_deref(),
+                client_os = client.os.as_deref(),
+                client_device = client.device.name(),
+                client_app = client.apps.first().map(display),
+                access_id_source = inbound.as_ref().map_or("generated", |v| v.source),
+                trace_flags = trace.map(|v| v.flags),
+                trace_state = trace.and_then(|v| v.state.as_deref()),
                 method = %parts.method,
+                route = %route,
                 uri = %parts.uri,
+                http_version = ?parts.version,
+                connection_id = connection.as_ref().map(|v| v.id),
+                connection_tls = connection.as_ref().map(|v| v.tls),
+                connection_proxy = connection.as_ref().and_then(|v| v.proxy).map(display),
+                connection_local = connection.as_ref().and_then(|v| v.local).map(display),
+                connection_requests,
                 "begin",
             );
-            if cfg!(debug_assertions) {
-                debug!(target: "request", headers=?parts.headers, "dump headers");
+            let header_log = self.state.header_log();
+            if header_log.request_enabled() && verbosity != AccessVerbosity::Off {
+                debug!(
+                    target: "request",
+                    headers = ?header_log.request_headers(&parts.headers),
+                    "dump headers",
+                );
             }
-            Request::from_parts(parts, body)
+            (Request::from_parts(parts, body), remote)
+        };
+
+        let body_capture = self.state.body_capture();
+        let body_capture = body_capture.capture().then(|| RequestBodyCapture {
+            policy: body_capture.clone(),
+            request: BodyCapture::new(body_capture.max_bytes()),
+        });
+        let request_bytes = ByteCount::default();
+        let req = req.map(|body| {
+            Body::new(AccessBody::new(
+                body,
+                request_bytes.clone(),
+                body_capture.as_ref().map(|v| v.request.clone()),
+                None,
+            ))
+        });
+
+        let meta = Arc::new(RequestMeta {
+            access_id: id,
+            remote,
+            method: req.method().clone(),
+            uri: req.uri().to_string(),
+            version: req.version(),
+            referer: header_str(req.headers(), header::REFERER),
+            user_agent: header_str(req.headers(), header::USER_AGENT),
+            route,
+            raw_path: req.uri().path().to_string(),
+            verbosity,
+            principal,
+        });
+
+        // remote is only missing here when request should be rejected
+        let inner = match remote.is_some()
+            || self.state.remote_addr().no_remote_policy == NoRemotePolicy::Unknown
+        {
+            true => InnerFuture::Next(self.inner.call(req)),
+            false => InnerFuture::Ready(Some(
+                RestResponse::<()>::fail(RestStatus::Unknown, id).into_response(),
+            )),
         };
 
-        AccessLogServiceOptFuture::Next(AccessLogServiceFuture::new(
-            req.uri().path().to_string(),
+        AccessLogServiceFuture::new(
+            meta,
             span,
-            self.inner.call(req),
-        ))
+            id_header,
+            self.state.clone(),
+            body_capture,
+            request_bytes,
+            inner,
+        )
     }
 }
 
-#[pin_project(project = AccessLogServiceOptFutureProj)]
-pub enum AccessLogServiceOptFuture<F> {
-    NoRemoteAddr { access_id: Uuid },
-    Next(#[pin] AccessLogServiceFuture<F>),
-}
-
-pub enum AccessLogServiceBody<B> {
-    NoRemoteAddr { access_id: Uuid },
-    Inner(Response<B>),
+#[pin_project(project = InnerFutureProj)]
+pub enum InnerFuture<F> {
+    Next(#[pin] F),
+    /// responded by access log itself, eg. no remote addr or cancelled
+    Ready(Option<Response>),
 }
 
-impl<B> IntoResponse for AccessLogServiceBody<B>
+impl<F, B, E> Future for InnerFuture<F>
 where
-    Response<B>: IntoResponse,
+    F: Future<Output = Result<Response<B>, E>>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
 {
-    fn into_response(self) -> Response {
-        match self {
-            AccessLogServiceBody::NoRemoteAddr { access_id } => {
-                RestResponse::<()>::fail(RestStatus::Unknown, access_id).into_response()
-            }
-            AccessLogServiceBody::Inner(inner) => inner.into_response(),
+    type Output = Result<Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            InnerFutureProj::Next(fut) => fut.poll(cx).map_ok(|v| v.map(Body::new)),
+            InnerFutureProj::Ready(response) => Poll::Ready(Ok(response
+                .take()
+                .expect("ready future polled after ready"))),
         }
     }
 }
 
-impl<F, B, E> Future for AccessLogServiceOptFuture<F>
-where
-    F: Future<Output = Result<Response<B>, E>>,
-    E: Debug,
-{
-    type Output = Result<AccessLogServiceBody<B>, E>;
+fn header_str(headers: &HeaderMap<HeaderValue>, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+}
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match self.project() {
-            AccessLogServiceOptFutureProj::NoRemoteAddr { access_id } => {
-                Poll::Ready(Ok(AccessLogServiceBody::NoRemoteAddr {
-                    access_id: *access_id,
-                }))
-            }
-            AccessLogServiceOptFutureProj::Next(fut) => {
-                fut.poll(cx).map_ok(AccessLogServiceBody::Inner)
-            }
+struct RequestMeta {
+    access_id: Uuid,
+    remote: Option<RemoteAddr>,
+    method: Method,
+    uri: String,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    route: String,
+    raw_path: String,
+    verbosity: AccessVerbosity,
+    principal: PrincipalSlot,
+}
+
+impl RequestMeta {
+    /// masked remote ip for failure events, begin event may have been skipped
+    fn remote_ip(&self, state: &HostState) -> Option<String> {
+        self.remote
+            .map(|v| state.remote_addr().privacy.mask(v.ip).to_string())
+    }
+
+    /// line of dedicated access sink & audit record
+    ///
+    /// every finished request, access rules only apply to request events
+    fn finished(&self, state: &HostState, outcome: RequestOutcome) {
+        let RequestOutcome {
+            status,
+            end_type,
+            request_bytes,
+            response_bytes,
+            cost,
+        } = outcome;
+        let principal = self.principal.get();
+        let user_id = principal.as_ref().map(|v| v.user_id.clone());
+        info!(
+            target: ACCESS_TARGET,
+            remote_ip = self.remote.map(|v| display(state.remote_addr().privacy.mask(v.ip))),
+            method = %self.method,
+            uri = %self.uri,
+            version = ?self.version,
+            status = status.as_u16(),
+            bytes = response_bytes,
+            request_bytes,
+            referer = self.referer.as_deref(),
+            user_agent = self.user_agent.as_deref(),
+            user_id = user_id.as_deref(),
+            tenant_id = principal.as_ref().and_then(|v| v.tenant_id.as_deref()),
+            access_id = %self.access_id,
+            route = %self.route,
+            end_type = end_type.name(),
+            cost = cost.as_millis(),
+            "access",
+        );
+        if let Some(audit) = state.access_audit() {
+            audit.record(AccessRecord {
+                access_id: self.access_id,
+                remote_ip: self.remote.map_or_else(
+                    || "unknown".to_string(),
+                    |v| state.remote_addr().privacy.mask(v.ip).to_string(),
+                ),
+                remote_port: self.remote.and_then(|v| v.port).map(i32::from),
+                method: self.method.to_string(),
+                route: self.route.clone(),
+                status: status.as_u16() as i16,
+                cost_ms: cost.as_millis() as i64,
+                user_id,
+                finished_at: Utc::now(),
+            });
+        }
+    }
+}
+
+struct RequestOutcome {
+    status: StatusCode,
+    end_type: RequestEndType,
+    request_bytes: u64,
+    response_bytes: Option<u64>,
+    cost: Duration,
+}
+
+struct RequestBodyCapture {
+    policy: BodyCapturePolicy,
+    request: BodyCapture,
+}
+
+impl RequestBodyCapture {
+    /// log request body, response body is logged once it finishes
+    fn finish(self) -> (BodyCapturePolicy, BodyCapture) {
+        let Self { policy, request } = self;
+        policy.log("request", request.snapshot());
+        let response = BodyCapture::new(policy.max_bytes());
+        (policy, response)
+    }
+}
+
+/// final event of a request, emitted once response body finishes
+struct ResponseFinish {
+    meta: Arc<RequestMeta>,
+    span: Span,
+    state: HostState,
+    status: StatusCode,
+    end_type: RequestEndType,
+    start: Instant,
+    request_bytes: ByteCount,
+    response_bytes: ByteCount,
+    capture: Option<(BodyCapturePolicy, BodyCapture)>,
+}
+
+impl ResponseFinish {
+    fn wrap<B>(self, body: B) -> Body
+    where
+        B: HttpBody<Data = Bytes> + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        Body::new(AccessBody::new(
+            body,
+            self.response_bytes.clone(),
+            self.capture.as_ref().map(|(_, v)| v.clone()),
+            Some(Box::new(move |complete| self.log(complete))),
+        ))
+    }
+
+    fn log(self, complete: bool) {
+        let _guard = self.span.enter();
+        if let Some((policy, capture)) = &self.capture {
+            policy.log("response", capture.snapshot());
         }
+
+        let cost = Instant::now().saturating_duration_since(self.start);
+        let request_bytes = self.request_bytes.get();
+        let response_bytes = self.response_bytes.get();
+        let verbosity = match self.end_type {
+            RequestEndType::Success => self.meta.verbosity,
+            _ => AccessVerbosity::Info,
+        };
+        access_event!(
+            verbosity,
+            target: "request",
+            request_phase = "finish",
+            request_end_type = self.end_type.name(),
+            status = self.status.as_u16(),
+            request_bytes,
+            response_bytes,
+            response_complete = complete,
+            cost = cost.as_millis(),
+            "finish",
+        );
+        self.meta.finished(
+            &self.state,
+            RequestOutcome {
+                status: self.status,
+                end_type: self.end_type,
+                request_bytes,
+                response_bytes: Some(response_bytes),
+                cost,
+            },
+        );
     }
 }
 
 #[pin_project(PinnedDrop)]
 pub struct AccessLogServiceFuture<F> {
-    pathname: String,
+    meta: Arc<RequestMeta>,
     span: Span,
+    id_header: AccessIdHeader,
+    state: HostState,
+    budget: Option<Duration>,
+    over_budget: Option<Pin<Box<Sleep>>>,
+    body_capture: Option<RequestBodyCapture>,
+    request_bytes: ByteCount,
+    inflight: Option<InFlightGuard>,
+    cancelled: Option<oneshot::Receiver<()>>,
     done: bool,
     start: Instant,
     #[pin]
-    inner: F,
+    inner: InnerFuture<F>,
 }
 
 impl<F> AccessLogServiceFuture<F> {
-    fn new(pathname: String, span: Span, inner: F) -> Self {
+    fn new(
+        meta: Arc<RequestMeta>,
+        span: Span,
+        id_header: AccessIdHeader,
+        state: HostState,
+        body_capture: Option<RequestBodyCapture>,
+        request_bytes: ByteCount,
+        inner: InnerFuture<F>,
+    ) -> Self {
+        let slow_request = state.slow_request();
+        let budget = slow_request.budget(&meta.route);
+        let over_budget = budget
+            .filter(|_| slow_request.mid_flight())
+            .map(|v| Box::pin(tokio::time::sleep(v)));
+        let (inflight, cancelled) = state.inflight().register(
+            meta.access_id,
+            meta.method.clone(),
+            meta.route.clone(),
+            meta.raw_path.clone(),
+            meta.remote,
+        );
         Self {
-            pathname,
+            meta,
             span,
+            id_header,
+            state,
+            budget,
+            over_budget,
+            body_capture,
+            request_bytes,
+            inflight: Some(inflight),
+            cancelled: Some(cancelled),
             done: false,
             start: Instant::now(),
             inner,
@@ -192,71 +598,227 @@ impl<F> AccessLogServiceFuture<F> {
     }
 }
 
+fn check_slow(meta: &RequestMeta, budget: Option<Duration>, cost: Duration, end_type: &str) {
+    if let Some(budget) = budget
+        && cost > budget
+    {
+        warn!(
+            target: "request",
+            request_phase = "slow",
+            request_end_type = end_type,
+            route = %meta.route,
+            raw_path = %meta.raw_path,
+            budget = budget.as_millis(),
+            cost = cost.as_millis(),
+            "request exceeded latency budget",
+        );
+    }
+}
+
 impl<F, B, E> Future for AccessLogServiceFuture<F>
 where
     F: Future<Output = Result<Response<B>, E>>,
     E: Debug,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
 {
-    type Output = Result<Response<B>, E>;
+    type Output = Result<Response, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let _guard = this.span.enter();
-        let result = ready!(this.inner.poll(cx));
+        let mut inner = this.inner;
+        if let Some(cancelled) = this.cancelled
+            && let Poll::Ready(received) = Pin::new(cancelled).poll(cx)
+        {
+            *this.cancelled = None;
+            // sender dropped without sending when registry entry goes away
+            if received.is_ok() {
+                warn!(
+                    target: "request",
+                    request_phase = "cancel",
+                    route = %this.meta.route,
+                    raw_path = %this.meta.raw_path,
+                    "request cancelled",
+                );
+                inner.set(InnerFuture::Ready(Some(
+                    RestResponse::<()>::fail(RestStatus::Cancelled, this.meta.access_id)
+                        .into_response(),
+                )));
+            }
+        }
+        let Poll::Ready(mut result) = inner.as_mut().poll(cx) else {
+            if let Some(over_budget) = this.over_budget
+                && over_budget.as_mut().poll(cx).is_ready()
+            {
+                *this.over_budget = None;
+                let cost = Instant::now().saturating_duration_since(*this.start);
+                warn!(
+                    target: "request",
+                    request_phase = "slow",
+                    request_end_type = "running",
+                    route = %this.meta.route,
+                    raw_path = %this.meta.raw_path,
+                    budget = this.budget.map(|v| v.as_millis()),
+                    cost = cost.as_millis(),
+                    "request still running past latency budget",
+                );
+            }
+            return Poll::Pending;
+        };
+        if let Ok(response) = &mut result {
+            this.id_header.apply(response);
+        }
+        let mut response_finish = None;
         if !*this.done {
             *this.done = true;
+            *this.inflight = None;
             let cost = Instant::now().saturating_duration_since(*this.start);
+            let (status, end_type) = match &result {
+                Ok(response) if (400..=599).contains(&response.status().as_u16()) => {
+                    (Some(response.status()), RequestEndType::Error)
+                }
+                Ok(response) => (Some(response.status()), RequestEndType::Success),
+                Err(_) => (None, RequestEndType::Error),
+            };
+            let meta = &*this.meta;
+            metrics::record_request(&meta.method, &meta.route, status, end_type, cost);
+            check_slow(meta, *this.budget, cost, end_type.name());
+            // skipped requests log bodies only when failed, like their other events
+            let is_error = end_type != RequestEndType::Success;
+            let capture = this
+                .body_capture
+                .take()
+                .filter(|v| {
+                    (is_error || meta.verbosity != AccessVerbosity::Off) && v.policy.keep(is_error)
+                })
+                .map(RequestBodyCapture::finish);
             match &result {
                 Ok(response) => {
+                    let response_headers = this
+                        .state
+                        .header_log()
+                        .response_headers(response.headers())
+                        .map(tracing::field::debug);
                     if (400..=599).contains(&response.status().as_u16()) {
                         error!(
                             target: "request",
                             request_phase = "end",
                             request_end_type = "error status",
-                            pathname = %this.pathname,
+                            remote_ip = meta.remote_ip(this.state),
+                            method = %meta.method,
+                            uri = %meta.uri,
+                            route = %meta.route,
+                            raw_path = %meta.raw_path,
                             status = response.status().as_u16(),
                             cost = cost.as_millis(),
+                            response_headers,
                             "end with error status"
                         );
                     } else {
-                        info!(
+                        access_event!(
+                            meta.verbosity,
                             target: "request",
                             request_phase = "end",
                             request_end_type = "success",
-                            pathname = %this.pathname,
+                            route = %meta.route,
+                            raw_path = %meta.raw_path,
                             status = response.status().as_u16(),
                             cost = cost.as_millis(),
+                            response_headers,
                             "end ok"
                         );
                     }
+                    response_finish = Some(ResponseFinish {
+                        meta: this.meta.clone(),
+                        span: this.span.clone(),
+                        state: this.state.clone(),
+                        status: response.status(),
+                        end_type,
+                        start: *this.start,
+                        request_bytes: this.request_bytes.clone(),
+                        response_bytes: ByteCount::default(),
+                        capture,
+                    });
                 }
                 Err(err) => {
+                    let request_bytes = this.request_bytes.get();
                     error!(
                         target: "request",
                         request_phase = "end",
                         request_end_type = "server error",
-                        pathname = %this.pathname,
+                        remote_ip = meta.remote_ip(this.state),
+                        method = %meta.method,
+                        uri = %meta.uri,
+                        route = %meta.route,
+                        raw_path = %meta.raw_path,
+                        request_bytes,
                         cost = cost.as_millis(),
                         "end with uncached error {:?}", err
                     );
+                    meta.finished(
+                        this.state,
+                        RequestOutcome {
+                            status: StatusCode::INTERNAL_SERVER_ERROR,
+                            end_type,
+                            request_bytes,
+                            response_bytes: None,
+                            cost,
+                        },
+                    );
                 }
             }
         }
-        Poll::Ready(result)
+        Poll::Ready(result.map(|response| match response_finish {
+            Some(finish) => response.map(|body| finish.wrap(body)),
+            None => response,
+        }))
     }
 }
 
 #[pinned_drop]
 impl<F> PinnedDrop for AccessLogServiceFuture<F> {
     fn drop(self: Pin<&mut Self>) {
-        if !self.done {
-            let _guard = self.span.enter();
-            let cost = Instant::now().saturating_duration_since(self.start);
+        let this = self.project();
+        if !*this.done {
+            let _guard = this.span.enter();
+            let cost = Instant::now().saturating_duration_since(*this.start);
+            let meta = &*this.meta;
+            metrics::record_request(
+                &meta.method,
+                &meta.route,
+                None,
+                RequestEndType::Dropped,
+                cost,
+            );
+            check_slow(meta, *this.budget, cost, RequestEndType::Dropped.name());
+            let request_bytes = this.request_bytes.get();
+            // nginx convention for client closed request
+            meta.finished(
+                this.state,
+                RequestOutcome {
+                    status: StatusCode::from_u16(499).expect("499 is a valid status"),
+                    end_type: RequestEndType::Dropped,
+                    request_bytes,
+                    response_bytes: None,
+                    cost,
+                },
+            );
+            if let Some(capture) = this.body_capture.take()
+                && capture.policy.keep(true)
+            {
+                capture.policy.log("request", capture.request.snapshot());
+            }
             warn!(
                 target: "request",
                 request_phase = "end",
                 request_end_type = "dropped",
-                pathname = %self.pathname,
+                remote_ip = meta.remote_ip(this.state),
+                method = %meta.method,
+                uri = %meta.uri,
+                route = %meta.route,
+                raw_path = %meta.raw_path,
+                request_bytes,
                 cost = cost.as_millis(),
                 "request connection dropped before finish",
             );
@@ -0,0 +1,134 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use axum::http::Method;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::scaffold::reload::{self, Reloadable};
+
+/// how "begin" & successful "end" events of a request are logged
+///
+/// error, dropped and slow requests are always logged
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum AccessVerbosity {
+    Info,
+    Debug,
+    Off,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AccessRuleAction {
+    Skip,
+    Debug,
+    /// log `percent` of requests, the rest behaves like `skip`
+    Sample {
+        percent: f64,
+    },
+}
+
+/// access log rule, first matched rule wins
+///
+/// eg. `{"route": "/gen_204", "action": "skip"}`,
+/// `{"method": "GET", "route": "/metrics", "action": "sample", "percent": 1}`
+#[derive(Clone, Debug, Deserialize)]
+pub struct AccessRule {
+    /// matched route template, any route when not set
+    pub route: Option<String>,
+    /// any method when not set
+    pub method: Option<String>,
+    #[serde(flatten)]
+    pub action: AccessRuleAction,
+}
+
+impl AccessRule {
+    fn matches(&self, method: &Method, route: &str) -> bool {
+        self.route.as_deref().is_none_or(|v| v == route)
+            && self
+                .method
+                .as_deref()
+                .is_none_or(|v| v.eq_ignore_ascii_case(method.as_str()))
+    }
+}
+
+#[derive(Default)]
+pub struct AccessRules {
+    path: Option<PathBuf>,
+    rules: Reloadable<(Option<SystemTime>, Vec<AccessRule>)>,
+}
+
+impl AccessRules {
+    /// load rules from json array file, no rules without a file
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let path = PathBuf::from(path);
+        let (modified, rules) = read_rules(&path)?;
+        info!(count = rules.len(), path = %path.display(), "access rules loaded");
+        Ok(Self {
+            path: Some(path),
+            rules: Reloadable::new((modified, rules)),
+        })
+    }
+
+    pub fn verbosity(&self, method: &Method, route: &str) -> AccessVerbosity {
+        let rules = self.rules.get();
+        match rules.1.iter().find(|v| v.matches(method, route)) {
+            None => AccessVerbosity::Info,
+            Some(rule) => match rule.action {
+                AccessRuleAction::Skip => AccessVerbosity::Off,
+                AccessRuleAction::Debug => AccessVerbosity::Debug,
+                AccessRuleAction::Sample { percent } => {
+                    if rand::random_bool((percent / 100.0).clamp(0.0, 1.0)) {
+                        AccessVerbosity::Info
+                    } else {
+                        AccessVerbosity::Off
+                    }
+                }
+            },
+        }
+    }
+
+    /// reload when rule file modified, broken file keeps current rules
+    pub fn reload(&self) -> Result<bool> {
+        let Some(path) = self.path.as_deref() else {
+            return Ok(false);
+        };
+
+        if reload::file_modified(path, "access rules")? == self.rules.get().0 {
+            return Ok(false);
+        }
+
+        let (modified, rules) = read_rules(path)?;
+        info!(count = rules.len(), path = %path.display(), "access rules reloaded");
+        self.rules.set((modified, rules));
+        Ok(true)
+    }
+
+    pub fn spawn_reload(self: Arc<Self>, every: Duration) {
+        if self.path.is_none() {
+            return;
+        }
+
+        reload::spawn_reload("access-rules", every, move || {
+            let rules = self.clone();
+            async move { rules.reload() }
+        });
+    }
+}
+
+fn read_rules(path: &Path) -> Result<(Option<SystemTime>, Vec<AccessRule>)> {
+    let modified = reload::file_modified(path, "access rules")?;
+    let content =
+        std::fs::read(path).with_context(|| format!("read access rules {}", path.display()))?;
+    let rules = serde_json::from_slice::<Vec<AccessRule>>(&content)
+        .with_context(|| format!("parse access rules {}", path.display()))?;
+    Ok((modified, rules))
+}
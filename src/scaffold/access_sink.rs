@@ -0,0 +1,151 @@
+use std::{fmt::Debug, io::Write};
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_appender::rolling::Rotation;
+use tracing_subscriber::{Layer, fmt::MakeWriter, layer::Context};
+
+/// target of events written to access sink, one event per finished request
+pub const ACCESS_TARGET: &str = "access";
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessLogFormat {
+    /// apache combined log format
+    Combined,
+    /// flat json object per line
+    Ndjson,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessLogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl From<AccessLogRotation> for Rotation {
+    fn from(value: AccessLogRotation) -> Self {
+        match value {
+            AccessLogRotation::Hourly => Rotation::HOURLY,
+            AccessLogRotation::Daily => Rotation::DAILY,
+            AccessLogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AccessSinkOptions {
+    pub format: AccessLogFormat,
+    pub rotation: AccessLogRotation,
+    pub max_files: usize,
+}
+
+/// writes `ACCESS_TARGET` events as lines, other events should be filtered out before
+pub struct AccessSinkLayer<W> {
+    format: AccessLogFormat,
+    writer: W,
+}
+
+impl<W> AccessSinkLayer<W> {
+    pub fn new(format: AccessLogFormat, writer: W) -> Self {
+        Self { format, writer }
+    }
+}
+
+impl<S, W> Layer<S> for AccessSinkLayer<W>
+where
+    S: Subscriber,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = FieldMap(Map::new());
+        event.record(&mut fields);
+        let line = match self.format {
+            AccessLogFormat::Combined => combined_line(&fields.0),
+            AccessLogFormat::Ndjson => {
+                let mut line = Map::new();
+                line.insert(
+                    "time".to_string(),
+                    Utc::now()
+                        .to_rfc3339_opts(SecondsFormat::Millis, true)
+                        .into(),
+                );
+                line.extend(fields.0);
+                Value::Object(line).to_string()
+            }
+        };
+        // nowhere to report a failed access line write
+        let _ = writeln!(self.writer.make_writer(), "{}", line);
+    }
+}
+
+/// `%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-agent}i"`
+fn combined_line(fields: &Map<String, Value>) -> String {
+    let field = |name: &str| match fields.get(name) {
+        Some(Value::String(v)) => v.clone(),
+        Some(v) => v.to_string(),
+        None => "-".to_string(),
+    };
+    format!(
+        "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+        field("remote_ip"),
+        escape(&field("user_id")),
+        Utc::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        field("method"),
+        escape(&field("uri")),
+        field("version"),
+        field("status"),
+        field("bytes"),
+        escape(&field("referer")),
+        escape(&field("user_agent")),
+    )
+}
+
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+struct FieldMap(Map<String, Value>);
+
+impl Visit for FieldMap {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        let value = match u64::try_from(value) {
+            Ok(v) => v.into(),
+            Err(_) => value.to_string().into(),
+        };
+        self.0.insert(field.name().to_string(), value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            return;
+        }
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::scaffold::access_body::CapturedBody;
+
+const REDACTED: &str = "[redacted]";
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BodyCaptureMode {
+    #[default]
+    Off,
+    /// log bodies of error status, server error or dropped requests
+    OnError,
+    /// log bodies of sampled requests
+    Sampled,
+    Always,
+}
+
+#[derive(Clone, Debug)]
+pub struct BodyCapturePolicy {
+    mode: BodyCaptureMode,
+    sample_rate: f64,
+    max_bytes: usize,
+    redact_keys: Arc<[String]>,
+}
+
+impl BodyCapturePolicy {
+    pub fn new(
+        mode: BodyCaptureMode,
+        sample_percent: f64,
+        max_bytes: usize,
+        redact_keys: Vec<String>,
+    ) -> Self {
+        Self {
+            mode,
+            sample_rate: (sample_percent / 100.0).clamp(0.0, 1.0),
+            max_bytes,
+            redact_keys: redact_keys.into_iter().map(|v| v.to_lowercase()).collect(),
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// decided on request begin, whether bodies of this request are buffered
+    pub fn capture(&self) -> bool {
+        match self.mode {
+            BodyCaptureMode::Off => false,
+            BodyCaptureMode::Sampled => rand::random_bool(self.sample_rate),
+            BodyCaptureMode::OnError | BodyCaptureMode::Always => true,
+        }
+    }
+
+    /// decided on response head, whether buffered bodies are logged
+    pub fn keep(&self, is_error: bool) -> bool {
+        match self.mode {
+            BodyCaptureMode::Off => false,
+            BodyCaptureMode::OnError => is_error,
+            BodyCaptureMode::Sampled | BodyCaptureMode::Always => true,
+        }
+    }
+
+    pub fn log(&self, kind: &'static str, captured: CapturedBody) {
+        info!(
+            target: "request",
+            request_phase = "body",
+            body_kind = kind,
+            body_bytes = captured.total,
+            body_truncated = captured.truncated,
+            body_complete = captured.complete,
+            body = %self.render(&captured),
+            "captured body",
+        );
+    }
+
+    /// json & form bodies are redacted, truncated json can't be redacted thus omitted
+    fn render(&self, captured: &CapturedBody) -> String {
+        let data = captured.data.as_slice();
+        if data.is_empty() {
+            return String::new();
+        }
+
+        if let Ok(mut value) = serde_json::from_slice::<Value>(data) {
+            self.redact_json(&mut value);
+            return value.to_string();
+        }
+
+        let text = String::from_utf8_lossy(data);
+        let trimmed = text.trim_start();
+        if trimmed.starts_with('{') || trimmed.starts_with('[') {
+            return format!("<unparsable json omitted, {} bytes>", data.len());
+        }
+
+        if !text.contains(char::is_whitespace) && text.contains('=') {
+            return url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(url::form_urlencoded::parse(data).map(|(k, v)| {
+                    if self.is_redacted(&k) {
+                        (k, REDACTED.into())
+                    } else {
+                        (k, v)
+                    }
+                }))
+                .finish();
+        }
+
+        text.into_owned()
+    }
+
+    fn is_redacted(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.redact_keys.contains(&key)
+    }
+
+    fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for (k, v) in object.iter_mut() {
+                    if self.is_redacted(k) {
+                        *v = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_json(v);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_json(v)),
+            _ => {}
+        }
+    }
+}
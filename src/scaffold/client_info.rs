@@ -0,0 +1,385 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+    str::FromStr,
+};
+
+use anyhow::{Context, Error, Result, anyhow};
+use axum::{
+    extract::{FromRequestParts, MatchedPath},
+    http::{header, request::Parts},
+};
+use serde::Deserialize;
+use tracing::{error, info};
+use uuid::Uuid;
+use woothee::{parser::Parser, woothee::VALUE_UNKNOWN};
+
+use crate::{
+    api::state::HostState,
+    scaffold::{
+        access_log::AccessLogId,
+        rest::{RestResponse, RestStatus},
+    },
+};
+
+/// `major.minor.patch`, missing parts are 0
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct AppVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FromStr for AppVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('.');
+        let mut next = |required: bool| match parts.next() {
+            None if !required => Ok(0),
+            v => v
+                .and_then(|v| v.parse::<u32>().ok())
+                .ok_or_else(|| anyhow!("invalid app version: {}", s)),
+        };
+        let version = Self {
+            major: next(true)?,
+            minor: next(false)?,
+            patch: next(false)?,
+        };
+        match parts.next() {
+            Some(_) => Err(anyhow!("invalid app version: {}", s)),
+            None => Ok(version),
+        }
+    }
+}
+
+impl Display for AppVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// `AppName/1.2.3` token of user agent
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientApp {
+    pub name: String,
+    pub version: AppVersion,
+}
+
+impl Display for ClientApp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.name, self.version)
+    }
+}
+
+/// woothee category
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum DeviceClass {
+    Pc,
+    Smartphone,
+    /// feature phone
+    Mobilephone,
+    /// eg. game console, tv
+    Appliance,
+    Crawler,
+    Misc,
+    #[default]
+    Unknown,
+}
+
+impl DeviceClass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pc => "pc",
+            Self::Smartphone => "smartphone",
+            Self::Mobilephone => "mobilephone",
+            Self::Appliance => "appliance",
+            Self::Crawler => "crawler",
+            Self::Misc => "misc",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// client platform parsed from `User-Agent`, inserted into request extensions by access log
+#[derive(Clone, Debug, Default)]
+pub struct ClientInfo {
+    pub browser: Option<String>,
+    pub browser_version: Option<String>,
+    pub os: Option<String>,
+    pub os_version: Option<String>,
+    pub device: DeviceClass,
+    /// known app tokens in user agent order
+    pub apps: Vec<ClientApp>,
+}
+
+impl ClientInfo {
+    pub fn app(&self, name: &str) -> Option<&ClientApp> {
+        self.apps.iter().find(|v| v.name == name)
+    }
+}
+
+/// minimum app version of a route, `{route}={app}/{version}`, eg. `/feed=HostApp/2.1.0`
+///
+/// checked by `ClientInfo` extractor, routes not extracting it accept any client
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MinAppVersion {
+    pub route: String,
+    pub app: String,
+    pub version: AppVersion,
+}
+
+impl FromStr for MinAppVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((route, app)) = s.rsplit_once('=') else {
+            return Err(anyhow!(
+                "min app version should be {{route}}={{app}}/{{version}}: {}",
+                s
+            ));
+        };
+        let Some((app, version)) = app.split_once('/') else {
+            return Err(anyhow!(
+                "min app version should be {{route}}={{app}}/{{version}}: {}",
+                s
+            ));
+        };
+        let version = version
+            .trim()
+            .parse()
+            .with_context(|| format!("parse min app version: {}", s))?;
+        Ok(Self {
+            route: route.trim().to_string(),
+            app: app.trim().to_string(),
+            version,
+        })
+    }
+}
+
+impl TryFrom<String> for MinAppVersion {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+pub struct ClientInfoPolicy {
+    parser: Parser,
+    /// app names recognized in user agent, others like `Mozilla/5.0` are ignored
+    apps: HashSet<String>,
+    min_versions: HashMap<String, (String, AppVersion)>,
+}
+
+impl ClientInfoPolicy {
+    /// apps named by `min_versions` are recognized too
+    pub fn new(apps: Vec<String>, min_versions: Vec<MinAppVersion>) -> Self {
+        Self {
+            parser: Parser::new(),
+            apps: apps
+                .into_iter()
+                .chain(min_versions.iter().map(|v| v.app.clone()))
+                .collect(),
+            min_versions: min_versions
+                .into_iter()
+                .map(|v| (v.route, (v.app, v.version)))
+                .collect(),
+        }
+    }
+
+    pub fn parse(&self, user_agent: &str) -> ClientInfo {
+        let known = |v: &str| (!v.is_empty() && v != VALUE_UNKNOWN).then(|| v.to_string());
+        let mut info = match self.parser.parse(user_agent) {
+            Some(parsed) => ClientInfo {
+                browser: known(parsed.name),
+                browser_version: known(parsed.version),
+                os: known(parsed.os),
+                os_version: known(&parsed.os_version),
+                device: match parsed.category {
+                    "pc" => DeviceClass::Pc,
+                    "smartphone" => DeviceClass::Smartphone,
+                    "mobilephone" => DeviceClass::Mobilephone,
+                    "appliance" => DeviceClass::Appliance,
+                    "crawler" => DeviceClass::Crawler,
+                    "misc" => DeviceClass::Misc,
+                    _ => DeviceClass::Unknown,
+                },
+                apps: Vec::new(),
+            },
+            None => ClientInfo::default(),
+        };
+
+        info.apps = user_agent
+            .split_ascii_whitespace()
+            .filter_map(|v| v.split_once('/'))
+            .filter(|(name, _)| self.apps.contains(*name))
+            .filter_map(|(name, version)| {
+                Some(ClientApp {
+                    name: name.to_string(),
+                    version: version.parse().ok()?,
+                })
+            })
+            .collect();
+        info
+    }
+
+    /// `Err` with minimum when client app is missing or older than route requires
+    fn check(&self, route: &str, info: &ClientInfo) -> Result<(), (&str, AppVersion)> {
+        let Some((app, min)) = self.min_versions.get(route) else {
+            return Ok(());
+        };
+        match info.app(app) {
+            Some(v) if v.version >= *min => Ok(()),
+            _ => Err((app, *min)),
+        }
+    }
+}
+
+impl FromRequestParts<HostState> for ClientInfo {
+    type Rejection = RestResponse;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &HostState,
+    ) -> Result<Self, Self::Rejection> {
+        let access_id = match parts.extensions.get::<AccessLogId>() {
+            Some(v) => v.uuid(),
+            None => {
+                error!("access id not found!");
+                return Err(RestResponse::fail(RestStatus::Unknown, Uuid::nil()));
+            }
+        };
+
+        // parsed by access log already
+        let policy = state.client_info();
+        let info = match parts.extensions.get::<ClientInfo>() {
+            Some(v) => v.clone(),
+            None => {
+                let user_agent = parts
+                    .headers
+                    .get(header::USER_AGENT)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default();
+                let info = policy.parse(user_agent);
+                parts.extensions.insert(info.clone());
+                info
+            }
+        };
+
+        let route = parts
+            .extensions
+            .get::<MatchedPath>()
+            .map(|v| v.as_str())
+            .unwrap_or_default();
+        if let Err((app, min)) = policy.check(route, &info) {
+            info!(
+                %app,
+                min_version = %min,
+                version = info.app(app).map(|v| v.version.to_string()),
+                "client app version below route minimum",
+            );
+            return Err(RestResponse::fail(RestStatus::BadRequest, access_id));
+        }
+
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u32, minor: u32, patch: u32) -> AppVersion {
+        AppVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    fn policy(min_versions: &[&str]) -> ClientInfoPolicy {
+        ClientInfoPolicy::new(
+            vec!["OtherApp".to_string()],
+            min_versions.iter().map(|v| v.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn app_version() {
+        assert_eq!("1.2.3".parse::<AppVersion>().unwrap(), version(1, 2, 3));
+        assert_eq!("1.2".parse::<AppVersion>().unwrap(), version(1, 2, 0));
+        assert_eq!("7".parse::<AppVersion>().unwrap(), version(7, 0, 0));
+        assert_eq!(version(1, 2, 0).to_string(), "1.2.0");
+        for garbage in [
+            "",
+            "1.",
+            ".1",
+            "1..2",
+            "1.2.3.4",
+            "1.x",
+            "v1.2",
+            "-1",
+            "1.2.3-beta",
+        ] {
+            assert!(garbage.parse::<AppVersion>().is_err(), "{}", garbage);
+        }
+        assert!(version(1, 10, 0) > version(1, 9, 9));
+    }
+
+    #[test]
+    fn min_app_version() {
+        let min = "/feed=HostApp/2.1".parse::<MinAppVersion>().unwrap();
+        assert_eq!(min.route, "/feed");
+        assert_eq!(min.app, "HostApp");
+        assert_eq!(min.version, version(2, 1, 0));
+        assert!("/feed".parse::<MinAppVersion>().is_err());
+        assert!("/feed=HostApp".parse::<MinAppVersion>().is_err());
+        assert!("/feed=HostApp/x".parse::<MinAppVersion>().is_err());
+    }
+
+    #[test]
+    fn app_tokens() {
+        let policy = policy(&["/feed=HostApp/2.1.0"]);
+        let info = policy.parse(
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) HostApp/2.1 Unknown/1.0 OtherApp/garbage",
+        );
+        // only known apps with valid versions
+        assert_eq!(
+            info.apps,
+            vec![ClientApp {
+                name: "HostApp".to_string(),
+                version: version(2, 1, 0),
+            }],
+        );
+        assert!(info.app("Unknown").is_none());
+        assert!(info.app("OtherApp").is_none());
+        assert!(info.app("Mozilla").is_none());
+
+        let info = policy.parse("OtherApp/3 HostApp/1.0.0");
+        assert_eq!(info.apps.len(), 2);
+        assert_eq!(info.apps[0].name, "OtherApp");
+        assert!(policy.parse("").apps.is_empty());
+    }
+
+    #[test]
+    fn check() {
+        let policy = policy(&["/feed=HostApp/2.1.0"]);
+        let check = |user_agent: &str, route: &str| policy.check(route, &policy.parse(user_agent));
+
+        assert_eq!(
+            check("HostApp/2.0.9", "/feed"),
+            Err(("HostApp", version(2, 1, 0))),
+        );
+        assert_eq!(check("HostApp/2.1.0", "/feed"), Ok(()));
+        assert_eq!(check("HostApp/2.1", "/feed"), Ok(()));
+        assert_eq!(check("HostApp/3.0.0", "/feed"), Ok(()));
+        // missing or unparsable app
+        assert!(check("OtherApp/9.0.0", "/feed").is_err());
+        assert!(check("HostApp/latest", "/feed").is_err());
+        assert!(check("", "/feed").is_err());
+        // route without minimum
+        assert_eq!(check("", "/other"), Ok(()));
+    }
+}
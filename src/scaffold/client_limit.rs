@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    BoxError,
+    body::Body,
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use http_body::Body as HttpBody;
+use ipnet::IpNet;
+use pin_project::pin_project;
+use tower::Service;
+use tower_layer::Layer;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    api::state::HostState,
+    scaffold::{
+        access_body::{AccessBody, ByteCount},
+        access_log::{AccessLogId, InnerFuture},
+        remote_addr::RemoteAddr,
+        rest::{RestResponse, RestStatus},
+    },
+};
+
+/// live count per ip, entry removed once count drops to zero
+#[derive(Clone, Default)]
+struct PerIpCounter {
+    counts: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl PerIpCounter {
+    /// always counted, returns count including this one
+    fn acquire(&self, ip: IpAddr) -> (PerIpPermit, usize) {
+        let mut counts = self.counts.lock().unwrap_or_else(|err| err.into_inner());
+        let count = counts.entry(ip).or_default();
+        *count += 1;
+        let count = *count;
+        (
+            PerIpPermit {
+                counter: self.clone(),
+                ip,
+            },
+            count,
+        )
+    }
+}
+
+struct PerIpPermit {
+    counter: PerIpCounter,
+    ip: IpAddr,
+}
+
+impl Drop for PerIpPermit {
+    fn drop(&mut self) {
+        let mut counts = self
+            .counter
+            .counts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// simultaneous connections per client ip, checked by listener on accept
+///
+/// over limit connections are answered a fixed 429 & closed before reaching axum
+#[derive(Clone, Default)]
+pub struct ConnectionLimit {
+    max_per_ip: Option<usize>,
+    /// eg. trusted proxies carrying many clients
+    exempt: Vec<IpNet>,
+    /// sent as `Retry-After` to over limit connections
+    pub retry_after: Duration,
+    counter: PerIpCounter,
+}
+
+impl ConnectionLimit {
+    pub fn new(max_per_ip: Option<usize>, exempt: Vec<IpNet>, retry_after: Duration) -> Self {
+        Self {
+            max_per_ip,
+            exempt,
+            retry_after,
+            counter: Default::default(),
+        }
+    }
+
+    /// `None` without limit or for exempt ip
+    pub fn acquire(&self, ip: IpAddr) -> Option<ConnectionPermit> {
+        let max = self.max_per_ip?;
+        let ip = ip.to_canonical();
+        if self.exempt.iter().any(|v| v.contains(&ip)) {
+            return None;
+        }
+        let (permit, count) = self.counter.acquire(ip);
+        Some(ConnectionPermit {
+            _permit: permit,
+            count,
+            over_limit: count > max,
+        })
+    }
+}
+
+/// held by accepted stream, released when connection closes
+pub struct ConnectionPermit {
+    _permit: PerIpPermit,
+    /// connections from ip including this one
+    pub count: usize,
+    pub over_limit: bool,
+}
+
+#[derive(Clone)]
+pub struct RequestLimitConfig {
+    /// concurrent in-flight requests per `RemoteAddr`, no limit when not set
+    pub max_per_ip: Option<usize>,
+    /// sent as `Retry-After` with `RateLimit`
+    pub retry_after: Duration,
+}
+
+/// responds `RateLimit` to requests over per ip concurrency
+#[derive(Clone)]
+pub struct RequestLimitLayer {
+    state: HostState,
+    config: RequestLimitConfig,
+    counter: PerIpCounter,
+}
+
+impl RequestLimitLayer {
+    pub fn new(state: HostState, config: RequestLimitConfig) -> Self {
+        Self {
+            state,
+            config,
+            counter: Default::default(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RequestLimitLayer {
+    type Service = RequestLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestLimitService {
+            inner,
+            state: self.state.clone(),
+            config: self.config.clone(),
+            counter: self.counter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestLimitService<S> {
+    inner: S,
+    state: HostState,
+    config: RequestLimitConfig,
+    counter: PerIpCounter,
+}
+
+impl<S> RequestLimitService<S> {
+    fn limited(&self, access_id: Uuid) -> Response {
+        RestResponse::<()>::fail(RestStatus::RateLimit, access_id)
+            .with_retry_after(self.config.retry_after)
+            .into_response()
+    }
+}
+
+impl<S, Resp> Service<Request> for RequestLimitService<S>
+where
+    S: Service<Request, Response = Response<Resp>>,
+    Resp: HttpBody<Data = Bytes> + Send + 'static,
+    Resp::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = RequestLimitFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let access_id = req
+            .extensions()
+            .get::<AccessLogId>()
+            .map(|v| v.uuid())
+            .unwrap_or_else(Uuid::nil);
+        let privacy = &self.state.remote_addr().privacy;
+
+        let (mut parts, body) = req.into_parts();
+        let remote = RemoteAddr::parse(
+            &mut parts.extensions,
+            &parts.headers,
+            self.state.remote_addr(),
+        )
+        .ok();
+        let req = Request::from_parts(parts, body);
+
+        let (Some(max), Some(remote)) = (self.config.max_per_ip, remote) else {
+            return RequestLimitFuture {
+                inner: InnerFuture::Next(self.inner.call(req)),
+                permit: None,
+            };
+        };
+        let (permit, count) = self.counter.acquire(remote.ip);
+        if count > max {
+            warn!(
+                remote_ip = %privacy.mask(remote.ip),
+                in_flight = count - 1,
+                max,
+                "request limit exceeded, request rejected",
+            );
+            return RequestLimitFuture {
+                inner: InnerFuture::Ready(Some(self.limited(access_id))),
+                permit: None,
+            };
+        }
+
+        RequestLimitFuture {
+            inner: InnerFuture::Next(self.inner.call(req)),
+            permit: Some(permit),
+        }
+    }
+}
+
+/// holds per ip permit until response body finishes or is dropped, like access log finish
+#[pin_project]
+pub struct RequestLimitFuture<F> {
+    #[pin]
+    inner: InnerFuture<F>,
+    permit: Option<PerIpPermit>,
+}
+
+impl<F, B, E> Future for RequestLimitFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Output = Result<Response, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = std::task::ready!(this.inner.poll(cx));
+        let Some(permit) = this.permit.take() else {
+            return Poll::Ready(output);
+        };
+        // streamed responses keep counting until done
+        Poll::Ready(output.map(|response| {
+            response.map(|body| {
+                Body::new(AccessBody::new(
+                    body,
+                    ByteCount::default(),
+                    None,
+                    Some(Box::new(move |_| drop(permit))),
+                ))
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{Ready, ready},
+    };
+
+    use axum::http::{StatusCode, header};
+
+    use super::*;
+    use crate::scaffold::{access_log::AccessLogConfig, remote_addr::RemoteAddrConfig};
+
+    /// responds a body which is not read until the test does
+    struct Handler;
+
+    impl Service<Request> for Handler {
+        type Response = Response;
+        type Error = Infallible;
+        type Future = Ready<Result<Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Request) -> Self::Future {
+            ready(Ok(Response::new(Body::from("streamed"))))
+        }
+    }
+
+    async fn call<S>(service: &mut S, ip: &str) -> Response
+    where
+        S: Service<Request, Response = Response, Error = Infallible>,
+    {
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut()
+            .insert(RemoteAddr::new(ip.parse().unwrap(), None));
+        service.call(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_permit_held_by_body() {
+        let state = HostState::new(RemoteAddrConfig::default(), AccessLogConfig::for_test());
+        let mut service = RequestLimitLayer::new(
+            state,
+            RequestLimitConfig {
+                max_per_ip: Some(1),
+                retry_after: Duration::from_secs(1),
+            },
+        )
+        .layer(Handler);
+
+        let streaming = call(&mut service, "192.0.2.1").await;
+        assert_eq!(streaming.status(), StatusCode::OK);
+
+        // first body still in flight
+        let limited = call(&mut service, "192.0.2.1").await;
+        assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(limited.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(
+            call(&mut service, "192.0.2.2").await.status(),
+            StatusCode::OK
+        );
+
+        let body = axum::body::to_bytes(streaming.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "streamed");
+        assert_eq!(
+            call(&mut service, "192.0.2.1").await.status(),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{extract::connect_info::Connected, serve::IncomingStream};
+
+use crate::scaffold::proxy_protocol::ProxyListener;
+
+static CONNECTION_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// accepted connection, attached to every request as `ConnectInfo<ConnectionInfo>`
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// sequence of accepted connections since start up
+    pub id: u64,
+    /// client addr, taken from PROXY protocol preamble when present
+    pub peer: SocketAddr,
+    /// socket peer when `peer` came from PROXY protocol preamble
+    pub proxy: Option<SocketAddr>,
+    /// listener addr the connection was accepted on
+    pub local: Option<SocketAddr>,
+    pub tls: bool,
+    requests: Arc<AtomicU64>,
+}
+
+impl ConnectionInfo {
+    pub fn new(
+        peer: SocketAddr,
+        proxy: Option<SocketAddr>,
+        local: Option<SocketAddr>,
+        tls: bool,
+    ) -> Self {
+        Self {
+            id: CONNECTION_SEQ.fetch_add(1, Ordering::Relaxed),
+            peer,
+            proxy,
+            local,
+            tls,
+            requests: Default::default(),
+        }
+    }
+
+    /// count a request on this connection, returns requests served including this one
+    pub fn next_request(&self) -> u64 {
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// plain tcp, tls is terminated before host if any
+impl Connected<IncomingStream<'_, ProxyListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, ProxyListener>) -> Self {
+        let addr = *stream.remote_addr();
+        Self::new(
+            addr.client,
+            addr.proxy,
+            stream.io().get_ref().local_addr().ok(),
+            false,
+        )
+    }
+}
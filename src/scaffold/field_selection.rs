@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::request::Parts,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::scaffold::{
+    access_log::AccessLogId,
+    pretty::Pretty,
+    rest::{RestResponse, RestStatus},
+};
+
+/// sparse fieldset requested by `?fields=a,b.c,items.id`
+///
+/// without `fields` query, selection keeps the whole body,
+/// paths are checked against `SelectableFields` of body type
+#[derive(Clone, Debug, Default)]
+pub struct FieldSelection {
+    root: Option<FieldNode>,
+}
+
+#[derive(Clone, Debug)]
+enum FieldNode {
+    All,
+    Fields(BTreeMap<String, FieldNode>),
+}
+
+impl FieldNode {
+    fn insert(&mut self, path: &[&str]) {
+        let Self::Fields(children) = self else {
+            return;
+        };
+
+        let Some((head, rest)) = path.split_first() else {
+            *self = Self::All;
+            return;
+        };
+
+        let child = children
+            .entry(head.to_string())
+            .or_insert_with(|| Self::Fields(BTreeMap::new()));
+        if rest.is_empty() {
+            *child = Self::All;
+        } else {
+            child.insert(rest);
+        }
+    }
+
+    /// `path` of every selected leaf
+    fn leaves(&self, path: &mut Vec<String>, leaves: &mut Vec<String>) {
+        let Self::Fields(children) = self else {
+            leaves.push(path.join("."));
+            return;
+        };
+        for (name, child) in children {
+            path.push(name.clone());
+            child.leaves(path, leaves);
+            path.pop();
+        }
+    }
+
+    /// fields currently omitted, eg. `None`, stay omitted
+    fn prune(&self, value: Value) -> Value {
+        let Self::Fields(children) = self else {
+            return value;
+        };
+
+        match value {
+            Value::Object(mut object) => {
+                let mut pruned = serde_json::Map::with_capacity(children.len());
+                for (name, child) in children {
+                    if let Some(v) = object.remove(name) {
+                        pruned.insert(name.clone(), child.prune(v));
+                    }
+                }
+                Value::Object(pruned)
+            }
+            Value::Array(items) => Value::Array(items.into_iter().map(|v| self.prune(v)).collect()),
+            // null or scalar, checked against schema already
+            value => value,
+        }
+    }
+}
+
+/// json field paths a body can be selected by, eg. `["id", "owner", "owner.name"]`
+///
+/// arrays are transparent, `items.id` selects `id` of every item
+pub trait SelectableFields {
+    const FIELDS: &'static [&'static str];
+}
+
+impl<T: SelectableFields> SelectableFields for Vec<T> {
+    const FIELDS: &'static [&'static str] = T::FIELDS;
+}
+
+impl<T: SelectableFields> SelectableFields for Option<T> {
+    const FIELDS: &'static [&'static str] = T::FIELDS;
+}
+
+impl FieldSelection {
+    pub fn parse(fields: &str) -> Result<Self, String> {
+        let mut root = FieldNode::Fields(BTreeMap::new());
+        for field in fields.split(',').map(|v| v.trim()) {
+            if field.is_empty() {
+                continue;
+            }
+            let path = field.split('.').collect::<Vec<_>>();
+            if path.iter().any(|v| v.is_empty()) {
+                return Err(format!("invalid field path: {}", field));
+            }
+            root.insert(&path);
+        }
+
+        // `?fields=` selects nothing, same as no selection
+        if let FieldNode::Fields(children) = &root
+            && children.is_empty()
+        {
+            return Ok(Self::default());
+        }
+        Ok(Self { root: Some(root) })
+    }
+
+    pub fn is_all(&self) -> bool {
+        matches!(self.root, None | Some(FieldNode::All))
+    }
+
+    /// first selected path not in `known`
+    pub fn check(&self, known: &[&str]) -> Result<(), String> {
+        let Some(root) = &self.root else {
+            return Ok(());
+        };
+        let mut leaves = Vec::new();
+        root.leaves(&mut Vec::new(), &mut leaves);
+        match leaves.into_iter().find(|v| !known.contains(&v.as_str())) {
+            Some(unknown) => Err(unknown),
+            None => Ok(()),
+        }
+    }
+
+    /// prune serialized `value`, fields are not checked
+    pub fn prune(&self, value: Value) -> Value {
+        match &self.root {
+            Some(root) => root.prune(value),
+            None => value,
+        }
+    }
+
+    /// prune response body, selecting a field unknown to `B` turns response into `BadRequest`
+    ///
+    /// checked against `B::FIELDS` rather than the body, so empty arrays & `ok_none` fail too
+    pub fn apply<B>(&self, response: RestResponse<B>) -> RestResponse<Value>
+    where
+        B: Serialize + SelectableFields,
+    {
+        let access_id = response.get_access_id();
+        if let Err(field) = self.check(B::FIELDS) {
+            info!(%field, "unknown field selected");
+            return RestResponse::fail(RestStatus::BadRequest, access_id);
+        }
+
+        let mut failed = false;
+        let response = response.map_body(|body| match serde_json::to_value(body) {
+            Ok(v) => self.prune(v),
+            Err(err) => {
+                info!(err=?Pretty(err), "serialize body for field selection error");
+                failed = true;
+                Value::Null
+            }
+        });
+
+        match failed {
+            true => RestResponse::fail(RestStatus::Unknown, access_id),
+            false => response,
+        }
+    }
+}
+
+impl<S> FromRequestParts<S> for FieldSelection
+where
+    S: Send + Sync,
+{
+    type Rejection = RestResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        #[derive(Deserialize)]
+        struct FieldsQuery {
+            fields: Option<String>,
+        }
+
+        let access_id = parts
+            .extensions
+            .get::<AccessLogId>()
+            .map(|v| v.uuid())
+            .unwrap_or_else(Uuid::nil);
+
+        let query = match Query::<FieldsQuery>::from_request_parts(parts, state).await {
+            Ok(v) => v.0,
+            Err(err) => {
+                info!(err=?Pretty(err), "extract fields query error");
+                return Err(RestResponse::fail(RestStatus::BadRequest, access_id));
+            }
+        };
+
+        match query.fields.as_deref() {
+            Some(fields) => Self::parse(fields).map_err(|err| {
+                info!(%err, "parse fields query error");
+                RestResponse::fail(RestStatus::BadRequest, access_id)
+            }),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::to_bytes, response::IntoResponse};
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Owner {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        email: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Repo {
+        id: u32,
+        owner: Option<Owner>,
+        tags: Vec<String>,
+    }
+
+    impl SelectableFields for Repo {
+        const FIELDS: &'static [&'static str] =
+            &["id", "owner", "owner.name", "owner.email", "tags"];
+    }
+
+    fn repo(id: u32) -> Repo {
+        Repo {
+            id,
+            owner: Some(Owner {
+                name: "a".to_string(),
+                email: None,
+            }),
+            tags: vec!["x".to_string()],
+        }
+    }
+
+    /// body of responded json, status of envelope when not ok
+    async fn apply<B: Serialize + SelectableFields>(
+        fields: &str,
+        body: Option<B>,
+    ) -> Result<Value, RestStatus> {
+        let selection = FieldSelection::parse(fields).unwrap();
+        let response = match body {
+            Some(body) => RestResponse::ok(Uuid::nil(), body),
+            None => RestResponse::ok_none(Uuid::nil()),
+        };
+        let response = selection.apply(response).into_response();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let mut json = serde_json::from_slice::<Value>(&bytes).unwrap();
+        match serde_json::from_value(json["status"].take()).unwrap() {
+            RestStatus::Ok => Ok(json["body"].take()),
+            status => Err(status),
+        }
+    }
+
+    #[tokio::test]
+    async fn nested_paths() {
+        assert_eq!(
+            apply("id,owner.name", Some(repo(1))).await,
+            Ok(json!({"id": 1, "owner": {"name": "a"}})),
+        );
+        assert_eq!(
+            apply("owner", Some(repo(1))).await,
+            Ok(json!({"owner": {"name": "a"}})),
+        );
+        assert_eq!(
+            apply("", Some(repo(1))).await.unwrap()["tags"],
+            json!(["x"])
+        );
+    }
+
+    #[tokio::test]
+    async fn arrays() {
+        assert_eq!(
+            apply("id", Some(vec![repo(1), repo(2)])).await,
+            Ok(json!([{"id": 1}, {"id": 2}])),
+        );
+        let mut unowned = repo(3);
+        unowned.owner = None;
+        assert_eq!(
+            apply("owner.name", Some(vec![unowned])).await,
+            Ok(json!([{"owner": null}])),
+        );
+    }
+
+    #[tokio::test]
+    async fn omitted_field_is_known() {
+        assert_eq!(
+            apply("owner.email", Some(repo(1))).await,
+            Ok(json!({"owner": {}})),
+        );
+        // no body responded without one
+        assert_eq!(apply::<Repo>("id", None).await, Ok(Value::Null));
+    }
+
+    #[tokio::test]
+    async fn unknown_field() {
+        assert_eq!(
+            apply("bogus", Some(repo(1))).await,
+            Err(RestStatus::BadRequest)
+        );
+        assert_eq!(
+            apply("id.bogus", Some(repo(1))).await,
+            Err(RestStatus::BadRequest)
+        );
+        assert_eq!(
+            apply("owner.bogus", Some(vec![repo(1)])).await,
+            Err(RestStatus::BadRequest)
+        );
+        // checked against type, not value
+        assert_eq!(
+            apply("bogus", Some(Vec::<Repo>::new())).await,
+            Err(RestStatus::BadRequest)
+        );
+        assert_eq!(
+            apply::<Repo>("bogus", None).await,
+            Err(RestStatus::BadRequest)
+        );
+        assert!(FieldSelection::parse("id..name").is_err());
+    }
+}
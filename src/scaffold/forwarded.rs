@@ -0,0 +1,244 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, Ipv6Addr},
+};
+
+use anyhow::{Result, anyhow};
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use crate::scaffold::remote_addr::RemoteAddr;
+
+/// node of `for` or `by` parameter
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ForwardedNode {
+    Addr(RemoteAddr),
+    Unknown,
+    /// eg. `_hidden`
+    Obfuscated(String),
+}
+
+/// one proxy hop of rfc 7239 `Forwarded` header
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ForwardedElement {
+    pub for_node: Option<ForwardedNode>,
+    pub by_node: Option<ForwardedNode>,
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+/// original scheme & host seen by the proxy facing client, set with `RemoteAddr`
+///
+/// empty without a trusted `Forwarded` header, set even when client hop is hidden
+#[derive(Clone, Debug, Default)]
+pub struct ForwardedInfo {
+    pub proto: Option<String>,
+    pub host: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ForwardedInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<ForwardedInfo>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`, elements in header order
+pub fn parse(value: &str) -> Result<Vec<ForwardedElement>> {
+    elements(value).into_iter().map(parse_element).collect()
+}
+
+/// unparsed elements in header order, eg. to parse only the ones needed
+pub fn elements(value: &str) -> Vec<&str> {
+    split_unquoted(value, ',')
+}
+
+/// `for=192.0.2.60;proto=http`, errors leave out header text as it holds client addrs
+pub fn parse_element(element: &str) -> Result<ForwardedElement> {
+    let mut parsed = ForwardedElement::default();
+    for pair in split_unquoted(element, ';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            return Err(anyhow!("forwarded pair without value"));
+        };
+        let value = unquote(value.trim())?;
+        match name.trim().to_ascii_lowercase().as_str() {
+            "for" => parsed.for_node = Some(parse_node(&value)?),
+            "by" => parsed.by_node = Some(parse_node(&value)?),
+            "proto" => parsed.proto = Some(value),
+            "host" => parsed.host = Some(value),
+            // extensions are allowed, ignored here
+            _ => {}
+        }
+    }
+    Ok(parsed)
+}
+
+/// split on `sep` outside quoted strings, empty parts are skipped
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == sep && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> Result<String> {
+    let Some(inner) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let Some(inner) = inner.strip_suffix('"') else {
+        return Err(anyhow!("unterminated forwarded quoted string"));
+    };
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    Ok(unquoted)
+}
+
+/// `192.0.2.60`, `192.0.2.60:80`, `[2001:db8::1]:4711`, `unknown` or `_hidden`
+///
+/// obfuscated port is dropped
+fn parse_node(value: &str) -> Result<ForwardedNode> {
+    if value.eq_ignore_ascii_case("unknown") {
+        return Ok(ForwardedNode::Unknown);
+    }
+    if value.starts_with('_') {
+        return Ok(ForwardedNode::Obfuscated(value.to_string()));
+    }
+
+    let (ip, port) = match value.strip_prefix('[') {
+        Some(rest) => {
+            let Some((ip, rest)) = rest.split_once(']') else {
+                return Err(anyhow!("unterminated forwarded ipv6 node"));
+            };
+            let ip = ip
+                .parse::<Ipv6Addr>()
+                .map_err(|_| anyhow!("invalid forwarded ipv6 node"))?;
+            let port = match rest {
+                "" => None,
+                rest => Some(
+                    rest.strip_prefix(':')
+                        .ok_or_else(|| anyhow!("invalid forwarded node port"))?,
+                ),
+            };
+            (IpAddr::V6(ip), port)
+        }
+        None => {
+            let (ip, port) = match value.split_once(':') {
+                Some((ip, port)) => (ip, Some(port)),
+                None => (value, None),
+            };
+            let ip = ip
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow!("invalid forwarded node"))?;
+            (ip, port)
+        }
+    };
+
+    let port = match port {
+        None => None,
+        Some(port) if port.starts_with('_') => None,
+        Some(port) => Some(
+            port.parse::<u16>()
+                .map_err(|_| anyhow!("invalid forwarded node port"))?,
+        ),
+    };
+    Ok(ForwardedNode::Addr(RemoteAddr::new(ip, port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(ip: &str, port: Option<u16>) -> Option<ForwardedNode> {
+        Some(ForwardedNode::Addr(RemoteAddr::new(
+            ip.parse().unwrap(),
+            port,
+        )))
+    }
+
+    #[test]
+    fn quoted_ipv6() {
+        let elements = parse(r#"for="[2001:db8:cafe::17]:4711";proto=https"#).unwrap();
+        assert_eq!(elements.len(), 1);
+        assert_eq!(elements[0].for_node, addr("2001:db8:cafe::17", Some(4711)));
+        assert_eq!(elements[0].proto.as_deref(), Some("https"));
+
+        let elements = parse(r#"For="[2001:db8:cafe::17]""#).unwrap();
+        assert_eq!(elements[0].for_node, addr("2001:db8:cafe::17", None));
+    }
+
+    #[test]
+    fn obfuscated() {
+        let elements = parse("for=_hidden, for=unknown;by=_SEVKISEK").unwrap();
+        assert_eq!(
+            elements[0].for_node,
+            Some(ForwardedNode::Obfuscated("_hidden".to_string()))
+        );
+        assert_eq!(elements[1].for_node, Some(ForwardedNode::Unknown));
+        assert_eq!(
+            elements[1].by_node,
+            Some(ForwardedNode::Obfuscated("_SEVKISEK".to_string()))
+        );
+
+        // obfuscated port is dropped
+        let elements = parse(r#"for="192.0.2.43:_gazonk", for="[2001:db8::1]:_p""#).unwrap();
+        assert_eq!(elements[0].for_node, addr("192.0.2.43", None));
+        assert_eq!(elements[1].for_node, addr("2001:db8::1", None));
+    }
+
+    #[test]
+    fn multiple_elements() {
+        let value =
+            r#"for=192.0.2.43;host="example.com";proto=http, for=198.51.100.17:80;by=203.0.113.60"#;
+        let elements = parse(value).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].for_node, addr("192.0.2.43", None));
+        assert_eq!(elements[0].host.as_deref(), Some("example.com"));
+        assert_eq!(elements[1].for_node, addr("198.51.100.17", Some(80)));
+        assert_eq!(elements[1].by_node, addr("203.0.113.60", None));
+
+        // separators inside quoted strings
+        let elements = parse(r#"for=192.0.2.43;ext="a,b;c", for=192.0.2.44"#).unwrap();
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[1].for_node, addr("192.0.2.44", None));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("for").is_err());
+        assert!(parse(r#"for="[2001:db8::1"#).is_err());
+        assert!(parse("for=[2001:db8::1").is_err());
+        assert!(parse("for=192.0.2.43:port").is_err());
+        assert!(parse("for=not-an-ip").is_err());
+    }
+}
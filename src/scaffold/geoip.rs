@@ -0,0 +1,250 @@
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use axum::{extract::FromRequestParts, http::request::Parts};
+use maxminddb::{MaxMindDBError, Reader};
+use serde::Deserialize;
+use tracing::{debug, info};
+
+use crate::scaffold::reload::{self, Reloadable};
+
+/// geo of `RemoteAddr`, inserted into request extensions by access log
+///
+/// empty without geoip database or remote addr
+#[derive(Clone, Debug, Default)]
+pub struct RemoteGeo {
+    /// iso 3166-1, eg. "US"
+    pub country: Option<String>,
+    /// iso 3166-2 subdivision without country, eg. "CA"
+    pub region: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+impl RemoteGeo {
+    fn merge(&mut self, record: GeoRecord) {
+        self.country = self
+            .country
+            .take()
+            .or(record.country.and_then(|v| v.iso_code));
+        self.region = self.region.take().or(record
+            .subdivisions
+            .and_then(|v| v.into_iter().next())
+            .and_then(|v| v.iso_code));
+        self.asn = self.asn.or(record.autonomous_system_number);
+        self.as_org = self.as_org.take().or(record.autonomous_system_organization);
+    }
+}
+
+impl<S> FromRequestParts<S> for RemoteGeo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<RemoteGeo>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// fields shared by GeoIP2/GeoLite2 country, city & asn databases
+#[derive(Deserialize)]
+struct GeoRecord {
+    country: Option<IsoCode>,
+    subdivisions: Option<Vec<IsoCode>>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IsoCode {
+    iso_code: Option<String>,
+}
+
+#[derive(Clone)]
+struct GeoIpDb {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    reader: Arc<Reader<Vec<u8>>>,
+}
+
+/// local `.mmdb` databases, eg. a city and an asn database, looked up in order
+#[derive(Default)]
+pub struct GeoIp {
+    paths: Vec<PathBuf>,
+    dbs: Reloadable<Vec<GeoIpDb>>,
+}
+
+impl GeoIp {
+    /// lookup disabled without a database
+    pub fn load(paths: &[String]) -> Result<Self> {
+        let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let dbs = paths
+            .iter()
+            .map(|v| open_db(v))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            paths,
+            dbs: Reloadable::new(dbs),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.paths.is_empty()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> RemoteGeo {
+        let dbs = self.dbs.get();
+        let mut geo = RemoteGeo::default();
+        for db in dbs.iter() {
+            match db.reader.lookup::<GeoRecord>(ip) {
+                Ok(record) => geo.merge(record),
+                Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+                Err(err) => debug!(%err, path = %db.path.display(), "geoip lookup error"),
+            }
+        }
+        geo
+    }
+
+    /// reopen databases whose file was replaced, broken file keeps current database
+    pub fn reload(&self) -> Result<bool> {
+        let dbs = self.dbs.get();
+        let mut reloaded = false;
+        let mut next = Vec::with_capacity(dbs.len());
+        for db in dbs.iter() {
+            if reload::file_modified(&db.path, "geoip database")? == db.modified {
+                next.push(db.clone());
+                continue;
+            }
+            next.push(open_db(&db.path)?);
+            reloaded = true;
+        }
+
+        if reloaded {
+            self.dbs.set(next);
+        }
+        Ok(reloaded)
+    }
+
+    pub fn spawn_reload(self: Arc<Self>, every: Duration) {
+        if !self.enabled() {
+            return;
+        }
+
+        reload::spawn_reload("geoip", every, move || {
+            let geoip = self.clone();
+            async move { geoip.reload() }
+        });
+    }
+}
+
+fn open_db(path: &Path) -> Result<GeoIpDb> {
+    let modified = reload::file_modified(path, "geoip database")?;
+    let reader = Reader::open_readfile(path)
+        .with_context(|| format!("open geoip database {}", path.display()))?;
+    info!(
+        path = %path.display(),
+        database_type = %reader.metadata.database_type,
+        build_epoch = reader.metadata.build_epoch,
+        "geoip database loaded",
+    );
+    Ok(GeoIpDb {
+        path: path.to_path_buf(),
+        modified,
+        reader: Arc::new(reader),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, time::Duration};
+
+    use super::*;
+
+    /// 192.0.2.0/24 in US-CA, 198.51.100.0/24 in DE
+    const CITY: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/geoip-city.mmdb"
+    );
+    /// 192.0.2.0/24 in AS64500 "Example Net"
+    const ASN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geoip-asn.mmdb");
+
+    fn ip(v: &str) -> IpAddr {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn lookup() {
+        let geoip = GeoIp::load(&[CITY.to_string(), ASN.to_string()]).unwrap();
+        assert!(geoip.enabled());
+
+        let geo = geoip.lookup(ip("192.0.2.1"));
+        assert_eq!(geo.country.as_deref(), Some("US"));
+        assert_eq!(geo.region.as_deref(), Some("CA"));
+        assert_eq!(geo.asn, Some(64500));
+        assert_eq!(geo.as_org.as_deref(), Some("Example Net"));
+
+        let geo = geoip.lookup(ip("198.51.100.7"));
+        assert_eq!(geo.country.as_deref(), Some("DE"));
+        assert_eq!(geo.region, None);
+        assert_eq!(geo.asn, None);
+
+        let geo = geoip.lookup(ip("203.0.113.1"));
+        assert_eq!(geo.country, None);
+        assert_eq!(geo.asn, None);
+    }
+
+    #[test]
+    fn disabled() {
+        let geoip = GeoIp::load(&[]).unwrap();
+        assert!(!geoip.enabled());
+        assert_eq!(geoip.lookup(ip("192.0.2.1")).country, None);
+        assert!(!geoip.reload().unwrap());
+    }
+
+    #[test]
+    fn hot_reload() {
+        let path = std::env::temp_dir().join(format!("geoip-reload-{}.mmdb", std::process::id()));
+        std::fs::copy(CITY, &path).unwrap();
+        let geoip = GeoIp::load(&[path.display().to_string()]).unwrap();
+        assert_eq!(geoip.lookup(ip("192.0.2.1")).country.as_deref(), Some("US"));
+        assert!(!geoip.reload().unwrap());
+
+        // replaced file, mtime moved on in case copy lands in the same tick
+        std::fs::copy(ASN, &path).unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(geoip.reload().unwrap());
+        let geo = geoip.lookup(ip("192.0.2.1"));
+        assert_eq!(geo.country, None);
+        assert_eq!(geo.asn, Some(64500));
+
+        // broken file keeps current database
+        std::fs::write(&path, b"not a database").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(10))
+            .unwrap();
+        assert!(geoip.reload().is_err());
+        assert_eq!(geoip.lookup(ip("192.0.2.1")).asn, Some(64500));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
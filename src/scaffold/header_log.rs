@@ -0,0 +1,95 @@
+use std::{
+    collections::HashSet,
+    fmt::{Debug, Formatter},
+};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use sha2::{Digest, Sha256};
+
+/// headers never logged in plain text unless removed from deny list
+pub const DEFAULT_DENIED_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+#[derive(Clone, Debug)]
+pub struct HeaderLogPolicy {
+    request: bool,
+    allow: HashSet<HeaderName>,
+    deny: HashSet<HeaderName>,
+    hash_denied: bool,
+    response: Vec<HeaderName>,
+}
+
+impl HeaderLogPolicy {
+    /// empty `allow` means every header not in `deny`
+    pub fn new(
+        request: bool,
+        allow: Vec<HeaderName>,
+        deny: Vec<HeaderName>,
+        hash_denied: bool,
+        response: Vec<HeaderName>,
+    ) -> Self {
+        Self {
+            request,
+            allow: allow.into_iter().collect(),
+            deny: deny.into_iter().collect(),
+            hash_denied,
+            response,
+        }
+    }
+
+    /// whether request headers are dumped on "begin"
+    pub fn request_enabled(&self) -> bool {
+        self.request
+    }
+
+    pub fn request_headers(&self, headers: &HeaderMap<HeaderValue>) -> HeaderDump {
+        HeaderDump(
+            headers
+                .iter()
+                .filter(|(name, _)| self.allow.is_empty() || self.allow.contains(*name))
+                .filter_map(|(name, value)| self.render(name, value))
+                .collect(),
+        )
+    }
+
+    /// selected response headers for "end", `None` if no header selected
+    pub fn response_headers(&self, headers: &HeaderMap<HeaderValue>) -> Option<HeaderDump> {
+        if self.response.is_empty() {
+            return None;
+        }
+        Some(HeaderDump(
+            self.response
+                .iter()
+                .flat_map(|name| headers.get_all(name).iter().map(move |v| (name, v)))
+                .filter_map(|(name, value)| self.render(name, value))
+                .collect(),
+        ))
+    }
+
+    fn render(&self, name: &HeaderName, value: &HeaderValue) -> Option<(String, String)> {
+        let value = if !self.deny.contains(name) {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        } else if self.hash_denied {
+            let digest = Sha256::digest(value.as_bytes());
+            format!("sha256:{}", hex::encode(&digest[..8]))
+        } else {
+            return None;
+        };
+        Some((name.as_str().to_string(), value))
+    }
+}
+
+pub struct HeaderDump(Vec<(String, String)>);
+
+impl Debug for HeaderDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(k, v)| (k, v)))
+            .finish()
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+use axum::{Extension, extract::State, http::Method};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    api::state::HostState,
+    scaffold::{
+        access_log::AccessLogId,
+        ip_privacy::IpPrivacy,
+        remote_addr::RemoteAddr,
+        rest::{RestPath, RestResponse},
+    },
+};
+
+struct InFlightEntry {
+    access_id: Uuid,
+    method: Method,
+    route: String,
+    raw_path: String,
+    remote: Option<RemoteAddr>,
+    start: Instant,
+    started_at: DateTime<Utc>,
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Serialize)]
+pub struct InFlightRequest {
+    pub access_id: Uuid,
+    pub method: String,
+    pub route: String,
+    pub raw_path: String,
+    pub remote_ip: Option<String>,
+    pub remote_port: Option<u16>,
+    pub started_at: String,
+    pub elapsed_ms: u64,
+}
+
+/// requests not yet responded, registered by access log
+///
+/// keyed by sequence, inbound access ids may repeat
+#[derive(Clone)]
+pub struct InFlightRegistry {
+    inner: Arc<InFlightInner>,
+}
+
+#[derive(Default)]
+struct InFlightInner {
+    privacy: IpPrivacy,
+    seq: AtomicU64,
+    requests: Mutex<HashMap<u64, InFlightEntry>>,
+}
+
+impl InFlightRegistry {
+    /// remote ips are listed & reported through `privacy`
+    pub fn new(privacy: IpPrivacy) -> Self {
+        Self {
+            inner: Arc::new(InFlightInner {
+                privacy,
+                ..Default::default()
+            }),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut HashMap<u64, InFlightEntry>) -> R) -> R {
+        let mut guard = self
+            .inner
+            .requests
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        f(&mut guard)
+    }
+
+    /// receiver resolves once request is cancelled, entry is removed when guard drops
+    pub fn register(
+        &self,
+        access_id: Uuid,
+        method: Method,
+        route: String,
+        raw_path: String,
+        remote: Option<RemoteAddr>,
+    ) -> (InFlightGuard, oneshot::Receiver<()>) {
+        let seq = self.inner.seq.fetch_add(1, Ordering::Relaxed);
+        let (cancel, cancelled) = oneshot::channel();
+        self.with(|requests| {
+            requests.insert(
+                seq,
+                InFlightEntry {
+                    access_id,
+                    method,
+                    route,
+                    raw_path,
+                    remote,
+                    start: Instant::now(),
+                    started_at: Utc::now(),
+                    cancel: Some(cancel),
+                },
+            )
+        });
+        (
+            InFlightGuard {
+                registry: self.clone(),
+                seq,
+            },
+            cancelled,
+        )
+    }
+
+    /// longest running first
+    pub fn list(&self) -> Vec<InFlightRequest> {
+        let now = Instant::now();
+        let mut requests = self.with(|requests| {
+            requests
+                .values()
+                .map(|v| InFlightRequest {
+                    access_id: v.access_id,
+                    method: v.method.to_string(),
+                    route: v.route.clone(),
+                    raw_path: v.raw_path.clone(),
+                    remote_ip: v.remote.map(|v| self.inner.privacy.mask(v.ip).to_string()),
+                    remote_port: v.remote.and_then(|v| v.port),
+                    started_at: v.started_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    elapsed_ms: now.saturating_duration_since(v.start).as_millis() as u64,
+                })
+                .collect::<Vec<_>>()
+        });
+        requests.sort_by_key(|v| Reverse(v.elapsed_ms));
+        requests
+    }
+
+    /// cancel every in-flight request with access id, returns count cancelled
+    pub fn cancel(&self, access_id: Uuid) -> usize {
+        self.with(|requests| {
+            requests
+                .values_mut()
+                .filter(|v| v.access_id == access_id)
+                .filter_map(|v| v.cancel.take())
+                .filter_map(|v| v.send(()).ok())
+                .count()
+        })
+    }
+
+    /// log requests still running, eg. on shutdown
+    pub fn report(&self) {
+        let requests = self.list();
+        info!(count = requests.len(), "in-flight requests");
+        for request in requests {
+            warn!(
+                access_id = %request.access_id,
+                method = %request.method,
+                route = %request.route,
+                raw_path = %request.raw_path,
+                remote_ip = request.remote_ip,
+                elapsed = request.elapsed_ms,
+                "request still in flight",
+            );
+        }
+    }
+}
+
+pub struct InFlightGuard {
+    registry: InFlightRegistry,
+    seq: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.registry.with(|requests| requests.remove(&self.seq));
+    }
+}
+
+/// `GET` admin handler, lists in-flight requests
+pub async fn list(
+    State(state): State<HostState>,
+    Extension(access_id): Extension<AccessLogId>,
+) -> RestResponse<Vec<InFlightRequest>> {
+    RestResponse::ok(access_id.uuid(), state.inflight().list())
+}
+
+#[derive(Serialize)]
+pub struct CancelResult {
+    pub cancelled: usize,
+}
+
+/// `DELETE` admin handler, cancels in-flight requests by access id
+pub async fn cancel(
+    State(state): State<HostState>,
+    Extension(access_id): Extension<AccessLogId>,
+    RestPath(target): RestPath<Uuid>,
+) -> RestResponse<CancelResult> {
+    let cancelled = state.inflight().cancel(target);
+    info!(target_access_id = %target, cancelled, "cancel in-flight request");
+    RestResponse::ok(access_id.uuid(), CancelResult { cancelled })
+}
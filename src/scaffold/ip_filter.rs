@@ -0,0 +1,236 @@
+use std::{
+    net::IpAddr,
+    ops::DerefMut,
+    path::PathBuf,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use axum::{
+    BoxError,
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use http_body::Body as HttpBody;
+use ipnet::IpNet;
+use redis::AsyncCommands;
+use serde::Deserialize;
+use tower::Service;
+use tower_layer::Layer;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    api::state::HostState,
+    scaffold::{
+        access_log::{AccessLogId, InnerFuture},
+        cache_init::{self, CachePool},
+        reload::{self, Reloadable},
+        remote_addr::RemoteAddr,
+        rest::{RestResponse, RestStatus},
+    },
+};
+
+/// eg. `{"allow": ["10.0.0.0/8"], "deny": ["10.1.2.3/32"]}`
+///
+/// deny wins, any addr not denied passes when allow is empty
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct IpFilterRules {
+    #[serde(default)]
+    pub allow: Vec<IpNet>,
+    #[serde(default)]
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilterRules {
+    /// unknown remote only passes without allow list
+    fn permits(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|v| v.contains(&ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|v| v.contains(&ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+enum IpFilterSource {
+    File(PathBuf),
+    /// json string at `key`
+    Redis {
+        pool: CachePool,
+        key: String,
+    },
+}
+
+/// reloadable allow & deny lists, shared by `IpFilterLayer`s
+pub struct IpFilter {
+    name: String,
+    source: Option<IpFilterSource>,
+    /// raw lists & parsed
+    rules: Reloadable<(Vec<u8>, IpFilterRules)>,
+}
+
+impl IpFilter {
+    /// `source` is a json file path or `redis://host/db#key`, everything passes without one
+    pub async fn load(name: &str, source: Option<&str>) -> Result<Self> {
+        let source = match source {
+            None => None,
+            Some(v) if v.starts_with("redis://") || v.starts_with("rediss://") => {
+                let (url, key) = v.split_once('#').unwrap_or((v, "host:ip-filter"));
+                let pool = cache_init::create(url, 1)
+                    .await
+                    .with_context(|| format!("create ip filter {} cache pool", name))?;
+                Some(IpFilterSource::Redis {
+                    pool,
+                    key: key.to_string(),
+                })
+            }
+            Some(v) => Some(IpFilterSource::File(PathBuf::from(v))),
+        };
+        let filter = Self {
+            name: name.to_string(),
+            source,
+            rules: Default::default(),
+        };
+        filter
+            .reload()
+            .await
+            .with_context(|| format!("load ip filter {}", name))?;
+        Ok(filter)
+    }
+
+    pub fn permits(&self, ip: Option<IpAddr>) -> bool {
+        self.rules.get().1.permits(ip)
+    }
+
+    /// reload when lists changed, broken lists keep current ones
+    pub async fn reload(&self) -> Result<bool> {
+        let content = match &self.source {
+            None => return Ok(false),
+            Some(IpFilterSource::File(path)) => {
+                std::fs::read(path).with_context(|| format!("read ip filter {}", path.display()))?
+            }
+            Some(IpFilterSource::Redis { pool, key }) => {
+                let mut conn = pool.get().await.context("connect to cache")?;
+                conn.deref_mut()
+                    .get::<_, Option<Vec<u8>>>(key)
+                    .await
+                    .with_context(|| format!("get ip filter {}", key))?
+                    .unwrap_or_default()
+            }
+        };
+        if self.rules.get().0 == content {
+            return Ok(false);
+        }
+
+        // missing redis key means no lists
+        let rules = match content.is_empty() {
+            true => IpFilterRules::default(),
+            false => serde_json::from_slice::<IpFilterRules>(&content)
+                .with_context(|| format!("parse ip filter {}", self.name))?,
+        };
+        info!(
+            name = %self.name,
+            allow = rules.allow.len(),
+            deny = rules.deny.len(),
+            "ip filter loaded",
+        );
+        self.rules.set((content, rules));
+        Ok(true)
+    }
+
+    pub fn spawn_reload(self: Arc<Self>, every: Duration) {
+        if self.source.is_none() {
+            return;
+        }
+
+        reload::spawn_reload(&format!("ip-filter-{}", self.name), every, move || {
+            let filter = self.clone();
+            async move { filter.reload().await }
+        });
+    }
+}
+
+/// responds `Forbidden` to requests from blocked `RemoteAddr`
+///
+/// as `route_layer` for a single router, or under `AccessLog` for every route
+#[derive(Clone)]
+pub struct IpFilterLayer {
+    state: HostState,
+    filter: Arc<IpFilter>,
+}
+
+impl IpFilterLayer {
+    pub fn new(state: HostState, filter: Arc<IpFilter>) -> Self {
+        Self { state, filter }
+    }
+}
+
+impl<S> Layer<S> for IpFilterLayer {
+    type Service = IpFilterService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IpFilterService {
+            inner,
+            state: self.state.clone(),
+            filter: self.filter.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IpFilterService<S> {
+    inner: S,
+    state: HostState,
+    filter: Arc<IpFilter>,
+}
+
+impl<S, Resp> Service<Request> for IpFilterService<S>
+where
+    S: Service<Request, Response = Response<Resp>>,
+    Resp: HttpBody<Data = Bytes> + Send + 'static,
+    Resp::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = InnerFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let (mut parts, body) = req.into_parts();
+        // cached in extensions by access log, unknown when remote can't be determined
+        let remote = RemoteAddr::parse(
+            &mut parts.extensions,
+            &parts.headers,
+            self.state.remote_addr(),
+        )
+        .ok();
+        let req = Request::from_parts(parts, body);
+
+        if self.filter.permits(remote.map(|v| v.ip)) {
+            return InnerFuture::Next(self.inner.call(req));
+        }
+
+        let access_id = req
+            .extensions()
+            .get::<AccessLogId>()
+            .map(|v| v.uuid())
+            .unwrap_or_else(Uuid::nil);
+        warn!(
+            name = %self.filter.name,
+            remote_ip = remote.map(|v| self.state.remote_addr().privacy.mask(v.ip).to_string()),
+            "request blocked by ip filter",
+        );
+        InnerFuture::Ready(Some(
+            RestResponse::<()>::fail(RestStatus::Forbidden, access_id).into_response(),
+        ))
+    }
+}
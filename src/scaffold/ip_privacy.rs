@@ -0,0 +1,149 @@
+use std::{
+    fmt::{Debug, Display, Formatter},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// request headers carrying client ips, kept out of header dumps when privacy is on
+pub const IP_HEADERS: &[&str] = &["forwarded", "x-forwarded-for", "x-real-ip"];
+
+/// how client ips show up in logs, access sink, audit & in-flight listing
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IpPrivacyMode {
+    #[default]
+    Off,
+    /// ipv4 to /24, ipv6 to /48
+    Truncate,
+    /// keyed hash, stable for a key but not reversible without it
+    Hash,
+}
+
+#[derive(Clone, Default)]
+pub struct IpPrivacy {
+    mode: IpPrivacyMode,
+    key: Arc<[u8]>,
+}
+
+impl Debug for IpPrivacy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IpPrivacy")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl IpPrivacy {
+    pub fn new(mode: IpPrivacyMode, key: Option<&str>) -> Result<Self> {
+        let key = match (mode, key) {
+            (IpPrivacyMode::Hash, None | Some("")) => {
+                return Err(anyhow!("ip privacy hash mode requires a key"));
+            }
+            (_, key) => key.unwrap_or_default().as_bytes().into(),
+        };
+        Ok(Self { mode, key })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode != IpPrivacyMode::Off
+    }
+
+    pub fn mask(&self, ip: IpAddr) -> MaskedIp {
+        let ip = ip.to_canonical();
+        match self.mode {
+            IpPrivacyMode::Off => MaskedIp::Plain(ip),
+            IpPrivacyMode::Truncate => MaskedIp::Plain(match ip {
+                IpAddr::V4(v) => Ipv4Addr::from(v.to_bits() & !0xff).into(),
+                IpAddr::V6(v) => Ipv6Addr::from(v.to_bits() & !((1 << 80) - 1)).into(),
+            }),
+            IpPrivacyMode::Hash => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key)
+                    .expect("hmac accepts keys of any size");
+                match ip {
+                    IpAddr::V4(v) => mac.update(&v.octets()),
+                    IpAddr::V6(v) => mac.update(&v.octets()),
+                }
+                let digest = mac.finalize().into_bytes();
+                let mut hashed = [0; 8];
+                hashed.copy_from_slice(&digest[..8]);
+                MaskedIp::Hashed(hashed)
+            }
+        }
+    }
+}
+
+/// ip as it may be logged
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MaskedIp {
+    Plain(IpAddr),
+    Hashed([u8; 8]),
+}
+
+impl Display for MaskedIp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Plain(ip) => Display::fmt(ip, f),
+            Self::Hashed(hashed) => write!(f, "hmac:{}", hex::encode(hashed)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(privacy: &IpPrivacy, ip: &str) -> String {
+        privacy.mask(ip.parse().unwrap()).to_string()
+    }
+
+    #[test]
+    fn off() {
+        let privacy = IpPrivacy::new(IpPrivacyMode::Off, None).unwrap();
+        assert!(!privacy.enabled());
+        assert_eq!(mask(&privacy, "192.0.2.77"), "192.0.2.77");
+        assert_eq!(mask(&privacy, "::ffff:192.0.2.77"), "192.0.2.77");
+    }
+
+    #[test]
+    fn truncate() {
+        let privacy = IpPrivacy::new(IpPrivacyMode::Truncate, None).unwrap();
+        assert_eq!(mask(&privacy, "192.0.2.77"), "192.0.2.0");
+        assert_eq!(mask(&privacy, "192.0.2.0"), "192.0.2.0");
+        assert_eq!(mask(&privacy, "255.255.255.255"), "255.255.255.0");
+        assert_eq!(
+            mask(&privacy, "2001:db8:abcd:12:3456::1"),
+            "2001:db8:abcd::"
+        );
+        assert_eq!(
+            mask(&privacy, "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff"),
+            "ffff:ffff:ffff::",
+        );
+        // mapped ipv6 is truncated as ipv4
+        assert_eq!(mask(&privacy, "::ffff:192.0.2.77"), "192.0.2.0");
+    }
+
+    #[test]
+    fn hash() {
+        assert!(IpPrivacy::new(IpPrivacyMode::Hash, None).is_err());
+        assert!(IpPrivacy::new(IpPrivacyMode::Hash, Some("")).is_err());
+
+        let privacy = IpPrivacy::new(IpPrivacyMode::Hash, Some("key")).unwrap();
+        let hashed = mask(&privacy, "192.0.2.77");
+        assert!(hashed.starts_with("hmac:"));
+        assert_eq!(hashed.len(), "hmac:".len() + 16);
+        assert!(!hashed.contains("192.0.2"));
+        // stable for a key, same for mapped ipv6
+        assert_eq!(mask(&privacy, "192.0.2.77"), hashed);
+        assert_eq!(mask(&privacy, "::ffff:192.0.2.77"), hashed);
+        assert_ne!(mask(&privacy, "192.0.2.78"), hashed);
+
+        let other = IpPrivacy::new(IpPrivacyMode::Hash, Some("other")).unwrap();
+        assert_ne!(mask(&other, "192.0.2.77"), hashed);
+    }
+}
@@ -0,0 +1,155 @@
+use std::{sync::LazyLock, time::Duration};
+
+use axum::{
+    http::{Method, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, TEXT_FORMAT, TextEncoder,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge,
+};
+use tokio::runtime::Handle;
+use tracing::error;
+
+use crate::scaffold::pretty::Pretty;
+
+static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        Opts::new("host_http_requests_total", "finished http requests"),
+        &["method", "route", "status_class", "end_type"]
+    )
+    .expect("register host_http_requests_total")
+});
+
+static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        HistogramOpts::new(
+            "host_http_request_duration_seconds",
+            "http request latency until response head"
+        ),
+        &["method", "route", "end_type"]
+    )
+    .expect("register host_http_request_duration_seconds")
+});
+
+static ACCESS_AUDIT_RECORDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        Opts::new(
+            "host_access_audit_records_total",
+            "access audit records by outcome"
+        ),
+        &["result"]
+    )
+    .expect("register host_access_audit_records_total")
+});
+
+static RUNTIME_WORKERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("host_tokio_workers", "tokio runtime worker threads")
+        .expect("register host_tokio_workers")
+});
+
+static RUNTIME_ALIVE_TASKS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("host_tokio_alive_tasks", "tokio runtime alive tasks")
+        .expect("register host_tokio_alive_tasks")
+});
+
+static RUNTIME_GLOBAL_QUEUE: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "host_tokio_global_queue_depth",
+        "tokio runtime global queue depth"
+    )
+    .expect("register host_tokio_global_queue_depth")
+});
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RequestEndType {
+    Success,
+    Error,
+    Dropped,
+}
+
+impl RequestEndType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Error => "error",
+            Self::Dropped => "dropped",
+        }
+    }
+}
+
+fn status_class(status: Option<StatusCode>) -> &'static str {
+    match status.map(|v| v.as_u16() / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        _ => "none",
+    }
+}
+
+/// extension methods are client chosen, folded to keep label cardinality bounded
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::CONNECT => "CONNECT",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::PATCH => "PATCH",
+        _ => "other",
+    }
+}
+
+pub fn record_request(
+    method: &Method,
+    route: &str,
+    status: Option<StatusCode>,
+    end_type: RequestEndType,
+    cost: Duration,
+) {
+    let method = method_label(method);
+    REQUESTS
+        .with_label_values(&[method, route, status_class(status), end_type.name()])
+        .inc();
+    REQUEST_DURATION
+        .with_label_values(&[method, route, end_type.name()])
+        .observe(cost.as_secs_f64());
+}
+
+/// `result` is one of "written", "dropped" or "failed"
+pub fn record_access_audit(result: &str, count: usize) {
+    ACCESS_AUDIT_RECORDS
+        .with_label_values(&[result])
+        .inc_by(count as u64);
+}
+
+fn update_runtime() {
+    let Ok(handle) = Handle::try_current() else {
+        return;
+    };
+    let metrics = handle.metrics();
+    RUNTIME_WORKERS.set(metrics.num_workers() as i64);
+    RUNTIME_ALIVE_TASKS.set(metrics.num_alive_tasks() as i64);
+    RUNTIME_GLOBAL_QUEUE.set(metrics.global_queue_depth() as i64);
+}
+
+/// `/metrics` handler, prometheus text format
+///
+/// process metrics come from the default registry on linux
+pub async fn serve() -> Response {
+    update_runtime();
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        error!(err=?Pretty(err), "encode metrics error");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    ([(CONTENT_TYPE, TEXT_FORMAT)], buffer).into_response()
+}
@@ -1,10 +1,32 @@
 #![allow(dead_code)]
 
+pub mod access_audit;
+pub mod access_body;
+pub mod access_id;
 pub mod access_log;
+pub mod access_rules;
+pub mod access_sink;
+pub mod body_capture;
 pub mod cache_init;
+pub mod client_info;
+pub mod client_limit;
+pub mod connection_info;
 pub mod database_init;
+pub mod field_selection;
+pub mod forwarded;
+pub mod geoip;
+pub mod header_log;
+pub mod inflight;
+pub mod ip_filter;
+pub mod ip_privacy;
+pub mod metrics;
 pub mod pretty;
+pub mod principal;
+pub mod proxy_protocol;
 pub mod quit_sig;
+pub mod reload;
 pub mod remote_addr;
 pub mod rest;
+pub mod rest_client;
+pub mod slow_request;
 pub mod tracing_output;
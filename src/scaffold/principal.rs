@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use tracing::{Span, error, info};
+use uuid::Uuid;
+
+use crate::scaffold::{
+    access_log::AccessLogId,
+    rest::{RestResponse, RestStatus},
+};
+
+/// authenticated caller of a request
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub user_id: String,
+    pub tenant_id: Option<String>,
+    /// eg. "session", "api-key"
+    pub auth_method: &'static str,
+}
+
+/// principal of current request, inserted into request extensions by access log
+///
+/// set by handlers or auth layers once caller is identified, recorded on request span
+#[derive(Clone)]
+pub struct PrincipalSlot {
+    span: Span,
+    principal: Arc<Mutex<Option<Principal>>>,
+}
+
+impl PrincipalSlot {
+    pub fn new(span: Span) -> Self {
+        Self {
+            span,
+            principal: Default::default(),
+        }
+    }
+
+    pub fn set(&self, principal: Principal) {
+        self.span.record("user_id", principal.user_id.as_str());
+        if let Some(tenant_id) = principal.tenant_id.as_deref() {
+            self.span.record("tenant_id", tenant_id);
+        }
+        self.span.record("auth_method", principal.auth_method);
+        info!(
+            user_id = %principal.user_id,
+            tenant_id = principal.tenant_id.as_deref(),
+            auth_method = principal.auth_method,
+            "principal identified",
+        );
+        *self.principal.lock().unwrap_or_else(|err| err.into_inner()) = Some(principal);
+    }
+
+    pub fn get(&self) -> Option<Principal> {
+        self.principal
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
+
+impl<S> FromRequestParts<S> for PrincipalSlot
+where
+    S: Send + Sync,
+{
+    type Rejection = RestResponse;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<PrincipalSlot>() {
+            Some(v) => Ok(v.clone()),
+            None => {
+                let access_id = parts
+                    .extensions
+                    .get::<AccessLogId>()
+                    .map(|v| v.uuid())
+                    .unwrap_or_else(Uuid::nil);
+                error!("principal slot not found!");
+                Err(RestResponse::fail(RestStatus::Unknown, access_id))
+            }
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use axum::serve::Listener;
+use bytes::{Buf, Bytes, BytesMut};
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::timeout,
+};
+use tracing::{debug, warn};
+
+use crate::scaffold::{
+    client_limit::{ConnectionLimit, ConnectionPermit},
+    ip_privacy::IpPrivacy,
+    pretty::Pretty,
+};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// longest v1 header including crlf
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+/// max time to write 429 to over limit connection before closing
+const OVER_LIMIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// haproxy PROXY protocol preamble handling on accept
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyProtocolMode {
+    /// plain tcp, preamble is not looked for
+    #[default]
+    Off,
+    /// parse v1 or v2 preamble from trusted proxies when present, plain connections pass through
+    Optional,
+    /// drop connections without a valid preamble or from untrusted peers
+    Strict,
+}
+
+/// client addr of accepted connection
+#[derive(Copy, Clone, Debug)]
+pub struct ProxiedAddr {
+    /// from preamble, socket peer otherwise
+    pub client: SocketAddr,
+    /// socket peer when preamble carried a client addr
+    pub proxy: Option<SocketAddr>,
+}
+
+/// `TcpListener` reading PROXY protocol preamble before handing connections to axum
+///
+/// preambles are read concurrently so a slow peer doesn't hold up accepting,
+/// only peers in `trusted_proxies` may send one
+pub struct ProxyListener {
+    inner: TcpListener,
+    mode: ProxyProtocolMode,
+    /// no peer when empty
+    trusted_proxies: Vec<IpNet>,
+    preamble_timeout: Duration,
+    /// peers & clients are logged through it
+    privacy: IpPrivacy,
+    connection_limit: ConnectionLimit,
+    pending: JoinSet<Option<(ProxiedStream, ProxiedAddr)>>,
+}
+
+impl ProxyListener {
+    pub fn new(
+        inner: TcpListener,
+        mode: ProxyProtocolMode,
+        trusted_proxies: Vec<IpNet>,
+        preamble_timeout: Duration,
+        privacy: IpPrivacy,
+        connection_limit: ConnectionLimit,
+    ) -> Self {
+        Self {
+            inner,
+            mode,
+            trusted_proxies,
+            preamble_timeout,
+            privacy,
+            connection_limit,
+            pending: JoinSet::new(),
+        }
+    }
+}
+
+impl ProxyListener {
+    /// count connection of client, `None` when over limit
+    ///
+    /// over limit connection is answered 429 & closed without waiting for a request
+    fn admit(
+        &self,
+        mut stream: ProxiedStream,
+        addr: ProxiedAddr,
+    ) -> Option<(ProxiedStream, ProxiedAddr)> {
+        stream.permit = self.connection_limit.acquire(addr.client.ip());
+        let Some(permit) = stream.permit.as_ref().filter(|v| v.over_limit) else {
+            return Some((stream, addr));
+        };
+        warn!(
+            client = %self.privacy.mask(addr.client.ip()),
+            connections = permit.count,
+            "connection limit exceeded, connection closed",
+        );
+        let response = format!(
+            "HTTP/1.1 429 Too Many Requests\r\nretry-after: {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            self.connection_limit.retry_after.as_secs(),
+        );
+        // permit is held until closed
+        tokio::spawn(async move {
+            let reject = async {
+                stream.write_all(response.as_bytes()).await?;
+                stream.shutdown().await
+            };
+            if !matches!(timeout(OVER_LIMIT_TIMEOUT, reject).await, Ok(Ok(()))) {
+                debug!("over limit connection gone before 429 was sent");
+            }
+        });
+        None
+    }
+}
+
+impl Listener for ProxyListener {
+    type Io = ProxiedStream;
+    type Addr = ProxiedAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                (stream, peer) = Listener::accept(&mut self.inner) => {
+                    let trusted = self
+                        .trusted_proxies
+                        .iter()
+                        .any(|v| v.contains(&peer.ip().to_canonical()));
+                    match (self.mode, trusted) {
+                        (ProxyProtocolMode::Off, _) | (ProxyProtocolMode::Optional, false) => {
+                            let admitted = self.admit(
+                                ProxiedStream::new(stream, Bytes::new()),
+                                ProxiedAddr { client: peer, proxy: None },
+                            );
+                            if let Some(admitted) = admitted {
+                                return admitted;
+                            }
+                            continue;
+                        }
+                        (ProxyProtocolMode::Strict, false) => {
+                            let peer = self.privacy.mask(peer.ip());
+                            warn!(%peer, "proxy protocol connection from untrusted peer dropped");
+                            continue;
+                        }
+                        (_, true) => {}
+                    }
+                    let strict = self.mode == ProxyProtocolMode::Strict;
+                    let preamble_timeout = self.preamble_timeout;
+                    let privacy = self.privacy.clone();
+                    self.pending.spawn(async move {
+                        let read = read_preamble(stream, peer, strict, &privacy);
+                        match timeout(preamble_timeout, read).await {
+                            Ok(Ok(accepted)) => Some(accepted),
+                            Ok(Err(err)) => {
+                                let peer = privacy.mask(peer.ip());
+                                warn!(err=?Pretty(err), %peer, "proxy protocol preamble rejected");
+                                None
+                            }
+                            Err(_) => {
+                                let peer = privacy.mask(peer.ip());
+                                warn!(%peer, "proxy protocol preamble timed out");
+                                None
+                            }
+                        }
+                    });
+                }
+                Some(joined) = self.pending.join_next() => {
+                    if let Ok(Some((stream, addr))) = joined
+                        && let Some(admitted) = self.admit(stream, addr)
+                    {
+                        return admitted;
+                    }
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        let local = self.inner.local_addr()?;
+        Ok(ProxiedAddr {
+            client: local,
+            proxy: None,
+        })
+    }
+}
+
+async fn read_preamble(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    strict: bool,
+    privacy: &IpPrivacy,
+) -> Result<(ProxiedStream, ProxiedAddr)> {
+    let mut buf = BytesMut::with_capacity(V1_MAX_LEN.max(V2_HEADER_LEN));
+
+    // read until version is known
+    let v2 = loop {
+        let v1_matched = prefix_matches(&buf, V1_PREFIX);
+        let v2_matched = prefix_matches(&buf, V2_SIGNATURE);
+        if !v1_matched && !v2_matched {
+            if strict {
+                return Err(anyhow!("connection without proxy protocol preamble"));
+            }
+            debug!(peer = %privacy.mask(peer.ip()), "no proxy protocol preamble, plain connection");
+            return Ok((
+                ProxiedStream::new(stream, buf.freeze()),
+                ProxiedAddr {
+                    client: peer,
+                    proxy: None,
+                },
+            ));
+        }
+        if v1_matched && buf.len() >= V1_PREFIX.len() {
+            break false;
+        }
+        if v2_matched && buf.len() >= V2_SIGNATURE.len() {
+            break true;
+        }
+        read_more(&mut stream, &mut buf).await?;
+    };
+
+    let client = if v2 {
+        while buf.len() < V2_HEADER_LEN {
+            read_more(&mut stream, &mut buf).await?;
+        }
+        let len = V2_HEADER_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        while buf.len() < len {
+            read_more(&mut stream, &mut buf).await?;
+        }
+        let header = buf.split_to(len);
+        parse_v2(&header)?
+    } else {
+        let end = loop {
+            if let Some(end) = buf.windows(2).position(|v| v == b"\r\n") {
+                break end + 2;
+            }
+            if buf.len() >= V1_MAX_LEN {
+                return Err(anyhow!("proxy protocol v1 header too long"));
+            }
+            read_more(&mut stream, &mut buf).await?;
+        };
+        let header = buf.split_to(end);
+        parse_v1(&header)?
+    };
+
+    debug!(
+        peer = %privacy.mask(peer.ip()),
+        client = client.map(|v| display(privacy.mask(v.ip()))),
+        version = if v2 { 2 } else { 1 },
+        "proxy protocol preamble parsed",
+    );
+    let addr = match client {
+        Some(client) => ProxiedAddr {
+            client,
+            proxy: Some(peer),
+        },
+        None => ProxiedAddr {
+            client: peer,
+            proxy: None,
+        },
+    };
+    Ok((ProxiedStream::new(stream, buf.freeze()), addr))
+}
+
+/// `buf` so far agrees with `prefix`
+fn prefix_matches(buf: &[u8], prefix: &[u8]) -> bool {
+    let len = buf.len().min(prefix.len());
+    buf[..len] == prefix[..len]
+}
+
+async fn read_more(stream: &mut TcpStream, buf: &mut BytesMut) -> Result<()> {
+    match stream.read_buf(buf).await? {
+        0 => Err(anyhow!("connection closed in proxy protocol preamble")),
+        _ => Ok(()),
+    }
+}
+
+/// `PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n`, `None` for `UNKNOWN`
+///
+/// errors leave out the line, it holds client addr
+fn parse_v1(header: &[u8]) -> Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(header)
+        .map_err(|_| anyhow!("proxy protocol v1 header is not ascii"))?
+        .trim_end_matches("\r\n");
+    let parts = line.split(' ').collect::<Vec<_>>();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        [
+            "PROXY",
+            "TCP4" | "TCP6",
+            src_ip,
+            _dst_ip,
+            src_port,
+            _dst_port,
+        ] => {
+            let ip = src_ip
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow!("invalid proxy protocol v1 source ip"))?;
+            let port = src_port
+                .parse::<u16>()
+                .map_err(|_| anyhow!("invalid proxy protocol v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(anyhow!("invalid proxy protocol v1 header")),
+    }
+}
+
+/// binary header, `None` for `LOCAL` command or non inet family
+fn parse_v2(header: &[u8]) -> Result<Option<SocketAddr>> {
+    let version = header[12] >> 4;
+    let command = header[12] & 0x0f;
+    if version != 2 {
+        return Err(anyhow!("unsupported proxy protocol version: {}", version));
+    }
+    match command {
+        // LOCAL, eg. health check from proxy itself
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(anyhow!("unsupported proxy protocol command: {}", command)),
+    }
+
+    let mut addrs = &header[V2_HEADER_LEN..];
+    match header[13] >> 4 {
+        // AF_INET
+        0x1 if addrs.len() >= 12 => {
+            let ip = Ipv4Addr::from(addrs.get_u32());
+            addrs.advance(4);
+            Ok(Some(SocketAddr::new(ip.into(), addrs.get_u16())))
+        }
+        // AF_INET6
+        0x2 if addrs.len() >= 36 => {
+            let ip = Ipv6Addr::from(addrs.get_u128());
+            addrs.advance(16);
+            Ok(Some(SocketAddr::new(ip.into(), addrs.get_u16())))
+        }
+        0x1 | 0x2 => Err(anyhow!("truncated proxy protocol v2 addresses")),
+        // AF_UNSPEC, AF_UNIX
+        _ => Ok(None),
+    }
+}
+
+/// accepted tcp stream, replays bytes read past the preamble first
+pub struct ProxiedStream {
+    inner: TcpStream,
+    buffered: Bytes,
+    permit: Option<ConnectionPermit>,
+}
+
+impl ProxiedStream {
+    fn new(inner: TcpStream, buffered: Bytes) -> Self {
+        Self {
+            inner,
+            buffered,
+            permit: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.inner
+    }
+}
+
+impl AsyncRead for ProxiedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.buffered.is_empty() {
+            let len = self.buffered.len().min(buf.remaining());
+            buf.put_slice(&self.buffered.split_to(len));
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for ProxiedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PROXY command, tcp over given family
+    fn v2_header(family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x21);
+        header.push(family << 4 | 0x1);
+        header.extend((addrs.len() as u16).to_be_bytes());
+        header.extend(addrs);
+        header
+    }
+
+    #[test]
+    fn v1() {
+        assert_eq!(
+            parse_v1(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n").unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap()),
+        );
+        assert_eq!(
+            parse_v1(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap()),
+        );
+        assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_v1(b"PROXY TCP4 192.0.2.1 192.0.2.2 56324\r\n").is_err());
+        assert!(parse_v1(b"PROXY TCP4 192.0.2.x 192.0.2.2 56324 443\r\n").is_err());
+        assert!(parse_v1(b"PROXY TCP4 192.0.2.1 192.0.2.2 65536 443\r\n").is_err());
+        assert!(parse_v1(b"PROXY UDP4 192.0.2.1 192.0.2.2 56324 443\r\n").is_err());
+    }
+
+    #[test]
+    fn v2() {
+        let mut addrs = vec![192, 0, 2, 1, 192, 0, 2, 2];
+        addrs.extend(56324u16.to_be_bytes());
+        addrs.extend(443u16.to_be_bytes());
+        assert_eq!(
+            parse_v2(&v2_header(0x1, &addrs)).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap()),
+        );
+        assert!(parse_v2(&v2_header(0x1, &addrs[..8])).is_err());
+
+        let mut addrs = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        addrs.extend("2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        addrs.extend(56324u16.to_be_bytes());
+        addrs.extend(443u16.to_be_bytes());
+        assert_eq!(
+            parse_v2(&v2_header(0x2, &addrs)).unwrap(),
+            Some("[2001:db8::1]:56324".parse().unwrap()),
+        );
+
+        // LOCAL
+        let mut local = v2_header(0x0, &[]);
+        local[12] = 0x20;
+        assert_eq!(parse_v2(&local).unwrap(), None);
+        // AF_UNIX
+        assert_eq!(parse_v2(&v2_header(0x3, &[0; 216])).unwrap(), None);
+        // version 1 in binary header
+        let mut version = v2_header(0x1, &[0; 12]);
+        version[12] = 0x11;
+        assert!(parse_v2(&version).is_err());
+    }
+
+    async fn listener(mode: ProxyProtocolMode, trusted: &str) -> (ProxyListener, SocketAddr) {
+        let inner = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = inner.local_addr().unwrap();
+        let listener = ProxyListener::new(
+            inner,
+            mode,
+            vec![trusted.parse().unwrap()],
+            Duration::from_secs(1),
+            Default::default(),
+            Default::default(),
+        );
+        (listener, addr)
+    }
+
+    /// connect & send `data`, connection is kept open by returned stream
+    async fn send(addr: SocketAddr, data: &[u8]) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(data).await.unwrap();
+        stream
+    }
+
+    async fn received(stream: &mut ProxiedStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    const PREAMBLE: &[u8] = b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n";
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\n";
+
+    #[tokio::test]
+    async fn optional_trusted() {
+        let (mut listener, addr) = listener(ProxyProtocolMode::Optional, "127.0.0.0/8").await;
+
+        let _client = send(addr, &[PREAMBLE, REQUEST].concat()).await;
+        let (mut stream, proxied) = listener.accept().await;
+        assert_eq!(proxied.client, "192.0.2.1:56324".parse().unwrap());
+        assert!(proxied.proxy.is_some());
+        assert_eq!(received(&mut stream, REQUEST.len()).await, REQUEST);
+
+        // plain connection passes through
+        let client = send(addr, REQUEST).await;
+        let (mut stream, proxied) = listener.accept().await;
+        assert_eq!(proxied.client, client.local_addr().unwrap());
+        assert!(proxied.proxy.is_none());
+        assert_eq!(received(&mut stream, REQUEST.len()).await, REQUEST);
+    }
+
+    #[tokio::test]
+    async fn optional_untrusted() {
+        let (mut listener, addr) = listener(ProxyProtocolMode::Optional, "10.0.0.0/8").await;
+
+        // preamble is not parsed, client can't choose its addr
+        let client = send(addr, PREAMBLE).await;
+        let (mut stream, proxied) = listener.accept().await;
+        assert_eq!(proxied.client, client.local_addr().unwrap());
+        assert!(proxied.proxy.is_none());
+        assert_eq!(received(&mut stream, PREAMBLE.len()).await, PREAMBLE);
+    }
+
+    #[tokio::test]
+    async fn strict_trusted() {
+        let (mut listener, addr) = listener(ProxyProtocolMode::Strict, "127.0.0.0/8").await;
+
+        // dropped without preamble, next connection is accepted
+        let _plain = send(addr, REQUEST).await;
+        let _proxied = send(addr, PREAMBLE).await;
+        let (_, proxied) = listener.accept().await;
+        assert_eq!(proxied.client, "192.0.2.1:56324".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn strict_untrusted() {
+        let (mut listener, addr) = listener(ProxyProtocolMode::Strict, "10.0.0.0/8").await;
+
+        let _client = send(addr, PREAMBLE).await;
+        let accepted = timeout(Duration::from_millis(200), listener.accept()).await;
+        assert!(accepted.is_err());
+    }
+
+    #[tokio::test]
+    async fn over_limit_connection_closed() {
+        let inner = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = inner.local_addr().unwrap();
+        let mut listener = ProxyListener::new(
+            inner,
+            ProxyProtocolMode::Off,
+            Vec::new(),
+            Duration::from_secs(1),
+            Default::default(),
+            ConnectionLimit::new(Some(1), Vec::new(), Duration::from_secs(2)),
+        );
+
+        let _first = send(addr, REQUEST).await;
+        let (first, _) = listener.accept().await;
+
+        // answered & closed without reaching axum, even before sending a request
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        let mut response = Vec::new();
+        let (accepted, read) = tokio::join!(
+            timeout(Duration::from_millis(200), listener.accept()),
+            second.read_to_end(&mut response),
+        );
+        assert!(accepted.is_err());
+        read.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 429 "));
+        assert!(response.contains("retry-after: 2\r\n"));
+
+        // permit released with first connection
+        drop(first);
+        let _third = send(addr, REQUEST).await;
+        let (mut stream, _) = listener.accept().await;
+        assert_eq!(received(&mut stream, REQUEST.len()).await, REQUEST);
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    future::Future,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use tokio::{spawn, time::interval};
+use tracing::{Instrument, error, info_span};
+
+use crate::scaffold::pretty::Pretty;
+
+/// current value of a reloadable source, swapped as a whole on reload
+#[derive(Default)]
+pub struct Reloadable<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(value)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        let guard = self.current.read().unwrap_or_else(|err| err.into_inner());
+        guard.clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.current.write().unwrap_or_else(|err| err.into_inner()) = Arc::new(value);
+    }
+}
+
+/// `what` names the file in errors, eg. "access rules"
+pub fn file_modified(path: &Path, what: &str) -> Result<Option<SystemTime>> {
+    let meta =
+        std::fs::metadata(path).with_context(|| format!("stat {} {}", what, path.display()))?;
+    Ok(meta.modified().ok())
+}
+
+/// call `reload` every `every`, failed reload is logged and keeps current value
+pub fn spawn_reload<F, Fut>(name: &str, every: Duration, mut reload: F)
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<bool>> + Send,
+{
+    spawn(
+        async move {
+            let mut ticker = interval(every);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = reload().await {
+                    error!(err=?Pretty(err), "reload error");
+                }
+            }
+        }
+        .instrument(info_span!("reload", name = %name)),
+    );
+}
@@ -5,6 +5,9 @@ use axum::{
     extract::{ConnectInfo, FromRequestParts},
     http::{Extensions, HeaderMap, HeaderValue, request::Parts},
 };
+use clap::ValueEnum;
+use ipnet::IpNet;
+use serde::Deserialize;
 use tracing::{debug, error, info, instrument};
 use uuid::Uuid;
 
@@ -12,74 +15,111 @@ use crate::{
     api::state::HostState,
     scaffold::{
         access_log::AccessLogId,
+        connection_info::ConnectionInfo,
+        forwarded::{self, ForwardedElement, ForwardedInfo, ForwardedNode},
+        ip_privacy::IpPrivacy,
         rest::{RestResponse, RestStatus},
     },
 };
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+/// what access log does when remote addr can't be determined
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NoRemotePolicy {
+    /// respond `Unknown` without reaching router
+    #[default]
+    Reject,
+    /// fall back to socket peer addr, reject without one
+    Peer,
+    /// continue without remote addr, `RemoteAddr` extractor still rejects
+    Unknown,
+}
+
+impl NoRemotePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Peer => "peer",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct RemoteAddr {
     pub ip: IpAddr,
     pub port: Option<u16>,
 }
 
+/// how remote addr is determined from connection & headers
+#[derive(Clone, Debug, Default)]
+pub struct RemoteAddrConfig {
+    /// eg. `X-Forwarded-For`, a single addr or a comma separated chain
+    ///
+    /// `Forwarded` is parsed as rfc 7239, also sets `ForwardedInfo`
+    pub header: Option<String>,
+    /// peers allowed to set remote header, no peer when empty
+    pub trusted_proxies: Vec<IpNet>,
+    pub no_remote_policy: NoRemotePolicy,
+    /// applied wherever remote ip is logged
+    pub privacy: IpPrivacy,
+}
+
+impl RemoteAddrConfig {
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|v| v.contains(&ip))
+    }
+}
+
 impl RemoteAddr {
+    /// ipv4-mapped ipv6 like `::ffff:1.2.3.4` is kept as ipv4
+    pub fn new(ip: IpAddr, port: Option<u16>) -> Self {
+        Self {
+            ip: ip.to_canonical(),
+            port,
+        }
+    }
+
     #[instrument("parse-remote", skip_all)]
     pub fn parse(
         extensions: &mut Extensions,
         headers: &HeaderMap<HeaderValue>,
-        header_name: Option<&str>,
+        config: &RemoteAddrConfig,
     ) -> Result<Self> {
         fn parse_inner(
-            extensions: &Extensions,
+            extensions: &mut Extensions,
             headers: &HeaderMap<HeaderValue>,
-            header_name: Option<&str>,
+            config: &RemoteAddrConfig,
         ) -> Result<RemoteAddr> {
-            // check header
-            if let Some(header_name) = header_name {
-                match headers.get(header_name) {
-                    Some(header_value) => {
-                        let Ok(header_str) = header_value.to_str() else {
-                            return Err(anyhow!(
-                                "can't extract remote from header value: {:#?}",
-                                header_value
-                            ));
-                        };
-
-                        if let Ok(addr) = header_str.parse::<SocketAddr>() {
-                            debug!(%header_name, ip=%addr.ip(), port=addr.port(), "remote header parsed as socket addr");
-                            return Ok(RemoteAddr {
-                                ip: addr.ip(),
-                                port: Some(addr.port()),
-                            });
-                        }
+            let peer = RemoteAddr::peer(extensions);
 
-                        if let Ok(addr) = header_str.parse::<IpAddr>() {
-                            debug!(%header_name, ip=%addr, "remote header parsed as ip addr");
-                            return Ok(RemoteAddr {
-                                ip: addr,
-                                port: None,
-                            });
+            // check header, only when set by a trusted proxy
+            if let Some(header_name) = config.header.as_deref() {
+                let trusted = peer.is_some_and(|peer| config.is_trusted(peer.ip));
+                if !trusted {
+                    debug!(%header_name, "peer is not a trusted proxy, remote header ignored");
+                } else {
+                    match RemoteAddr::parse_chain(headers, header_name, config)? {
+                        Some((addr, forwarded)) => {
+                            // kept even when client hop is hidden
+                            if let Some(forwarded) = forwarded {
+                                extensions.insert(forwarded);
+                            }
+                            if let Some(addr) = addr {
+                                return Ok(addr);
+                            }
+                            info!("client hop of remote header is unknown or obfuscated");
                         }
-
-                        return Err(anyhow!(
-                            "can't extract addr from header value: {:#?}",
-                            header_str
-                        ));
+                        None => info!("can't extract remote from header"),
                     }
-                    None => {
-                        info!("can't extract remote from header");
-                    }
-                };
+                }
             }
 
             // check direct
-            match extensions.get::<ConnectInfo<SocketAddr>>() {
-                Some(ConnectInfo(addr)) => {
-                    debug!(%addr, "remote connect info found");
-                    Ok(RemoteAddr {
-                        ip: addr.ip(),
-                        port: Some(addr.port()),
-                    })
+            match peer {
+                Some(addr) => {
+                    debug!(ip=%config.privacy.mask(addr.ip), port=?addr.port, "remote connect info found");
+                    Ok(addr)
                 }
                 None => Err(anyhow!("no remote header or connect info can be used")),
             }
@@ -91,11 +131,105 @@ impl RemoteAddr {
         }
 
         // run parse
-        let parsed = parse_inner(extensions, headers, header_name)?;
+        let parsed = parse_inner(extensions, headers, config)?;
         extensions.insert(parsed);
         Ok(parsed)
     }
 
+    /// walk `client, proxy1, proxy2` right to left, first untrusted hop is the client
+    ///
+    /// leftmost hop when every hop is trusted, `None` without header,
+    /// no addr when the client hop is `unknown` or obfuscated
+    ///
+    /// hops are parsed while walking, client supplied ones left of the client are never looked at
+    fn parse_chain(
+        headers: &HeaderMap<HeaderValue>,
+        header_name: &str,
+        config: &RemoteAddrConfig,
+    ) -> Result<Option<(Option<Self>, Option<ForwardedInfo>)>> {
+        let rfc7239 = header_name.eq_ignore_ascii_case("forwarded");
+        // `None` for value which is not visible ascii
+        let mut hops = Vec::new();
+        for header_value in headers.get_all(header_name) {
+            match header_value.to_str() {
+                Ok(v) if rfc7239 => hops.extend(forwarded::elements(v).into_iter().map(Some)),
+                Ok(v) => hops.extend(
+                    v.split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(Some),
+                ),
+                Err(_) => hops.push(None),
+            }
+        }
+        let parse = |hop: Option<&str>| -> Result<ForwardedElement> {
+            let Some(hop) = hop else {
+                return Err(anyhow!("remote header value is not visible ascii"));
+            };
+            match rfc7239 {
+                true => forwarded::parse_element(hop),
+                false => Ok(ForwardedElement {
+                    for_node: Some(ForwardedNode::Addr(Self::parse_hop(hop)?)),
+                    ..Default::default()
+                }),
+            }
+        };
+
+        let mut client = None;
+        for hop in hops.iter().rev() {
+            let element = parse(*hop)?;
+            let trusted = matches!(
+                element.for_node,
+                Some(ForwardedNode::Addr(addr)) if config.is_trusted(addr.ip)
+            );
+            client = Some(element);
+            if !trusted {
+                break;
+            }
+        }
+        let Some(client) = client else {
+            return Ok(None);
+        };
+        let addr = match client.for_node {
+            Some(ForwardedNode::Addr(addr)) => Some(addr),
+            _ => None,
+        };
+        debug!(
+            %header_name,
+            hops = hops.len(),
+            ip = addr.map(|v| display(config.privacy.mask(v.ip))),
+            port = ?addr.and_then(|v| v.port),
+            "remote header parsed",
+        );
+
+        let info = rfc7239.then_some(ForwardedInfo {
+            proto: client.proto,
+            host: client.host,
+        });
+        Ok(Some((addr, info)))
+    }
+
+    fn parse_hop(hop: &str) -> Result<Self> {
+        if let Ok(addr) = hop.parse::<SocketAddr>() {
+            return Ok(RemoteAddr::new(addr.ip(), Some(addr.port())));
+        }
+
+        if let Ok(addr) = hop.parse::<IpAddr>() {
+            return Ok(RemoteAddr::new(addr, None));
+        }
+
+        Err(anyhow!("can't extract addr from header hop"))
+    }
+
+    /// socket peer addr, ignoring remote header
+    pub fn peer(extensions: &Extensions) -> Option<Self> {
+        extensions
+            .get::<ConnectInfo<ConnectionInfo>>()
+            .map(|ConnectInfo(connection)| {
+                RemoteAddr::new(connection.peer.ip(), Some(connection.peer.port()))
+            })
+    }
+
     pub fn save_extension(self, extensions: &mut Extensions) {
         extensions.insert(self);
     }
@@ -119,7 +253,7 @@ impl FromRequestParts<HostState> for RemoteAddr {
         };
 
         // parse
-        let addr = Self::parse(&mut parts.extensions, &parts.headers, state.remote_header())
+        let addr = Self::parse(&mut parts.extensions, &parts.headers, state.remote_addr())
             .map_err(|err| {
                 info!(err=%err, "parse remote addr error");
                 RestResponse::fail(RestStatus::Unknown, access_id)
@@ -132,3 +266,118 @@ impl FromRequestParts<HostState> for RemoteAddr {
         Ok(addr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderName;
+
+    use super::*;
+
+    fn config(header: &str, trusted: &[&str]) -> RemoteAddrConfig {
+        RemoteAddrConfig {
+            header: Some(header.to_string()),
+            trusted_proxies: trusted.iter().map(|v| v.parse().unwrap()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn chain(config: &RemoteAddrConfig, values: &[&str]) -> Result<Option<RemoteAddr>> {
+        let header_name = config.header.as_deref().unwrap();
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(
+                HeaderName::from_bytes(header_name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+        }
+        RemoteAddr::parse_chain(&headers, header_name, config).map(|v| v.and_then(|(addr, _)| addr))
+    }
+
+    fn addr(ip: &str, port: Option<u16>) -> Option<RemoteAddr> {
+        Some(RemoteAddr::new(ip.parse().unwrap(), port))
+    }
+
+    #[test]
+    fn first_untrusted_from_right() {
+        let config = config("x-forwarded-for", &["10.0.0.0/8"]);
+        assert_eq!(
+            chain(&config, &["198.51.100.1, 203.0.113.5, 10.0.0.2"]).unwrap(),
+            addr("203.0.113.5", None),
+        );
+        assert_eq!(
+            chain(&config, &["198.51.100.1", "203.0.113.5:4711, 10.0.0.2"]).unwrap(),
+            addr("203.0.113.5", Some(4711)),
+        );
+        // every hop trusted
+        assert_eq!(
+            chain(&config, &["10.0.0.3, 10.0.0.2"]).unwrap(),
+            addr("10.0.0.3", None),
+        );
+        assert_eq!(chain(&config, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn hops_left_of_client_not_parsed() {
+        let config = config("x-forwarded-for", &["10.0.0.0/8"]);
+        assert_eq!(
+            chain(&config, &["garbage, unknown, 203.0.113.5"]).unwrap(),
+            addr("203.0.113.5", None),
+        );
+        assert_eq!(
+            chain(&config, &["garbage", "203.0.113.5, 10.0.0.2"]).unwrap(),
+            addr("203.0.113.5", None),
+        );
+        assert_eq!(
+            chain(&config, &["garbage, ::ffff:203.0.113.5"]).unwrap(),
+            addr("203.0.113.5", None),
+        );
+    }
+
+    #[test]
+    fn header_ignored_without_trusted_proxies() {
+        let config = config("x-forwarded-for", &[]);
+        let mut headers = HeaderMap::new();
+        headers.append("x-forwarded-for", HeaderValue::from_static("192.0.2.10"));
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(ConnectionInfo::new(
+            "203.0.113.5:4711".parse().unwrap(),
+            None,
+            None,
+            false,
+        )));
+
+        let remote = RemoteAddr::parse(&mut extensions, &headers, &config).unwrap();
+        assert_eq!(Some(remote), addr("203.0.113.5", Some(4711)));
+    }
+
+    #[test]
+    fn forwarded_hidden_client_falls_back_to_peer() {
+        let config = config("forwarded", &["10.0.0.0/8"]);
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "forwarded",
+            HeaderValue::from_static(
+                "for=garbage, for=_hidden;proto=https;host=example.com, for=10.0.0.3",
+            ),
+        );
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(ConnectionInfo::new(
+            "10.0.0.2:4711".parse().unwrap(),
+            None,
+            None,
+            false,
+        )));
+
+        let remote = RemoteAddr::parse(&mut extensions, &headers, &config).unwrap();
+        assert_eq!(Some(remote), addr("10.0.0.2", Some(4711)));
+        let forwarded = extensions.get::<ForwardedInfo>().unwrap();
+        assert_eq!(forwarded.proto.as_deref(), Some("https"));
+        assert_eq!(forwarded.host.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn malformed_client_hop() {
+        let config = config("x-forwarded-for", &["10.0.0.0/8"]);
+        assert!(chain(&config, &["203.0.113.5, garbage, 10.0.0.2"]).is_err());
+    }
+}
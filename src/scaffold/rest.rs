@@ -6,7 +6,11 @@ use axum::{
     http::{StatusCode, request::Parts},
     response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
 };
-use axum_extra::{TypedHeader, extract::CookieJar, headers::CacheControl};
+use axum_extra::{
+    TypedHeader,
+    extract::CookieJar,
+    headers::{CacheControl, RetryAfter},
+};
 use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
 use serde_json::json;
 use tracing::info;
@@ -71,6 +75,12 @@ pub enum RestStatus {
     Ok = 0,
     Unknown,
     BadRequest,
+    /// cancelled before handler finished, eg. by admin
+    Cancelled,
+    /// remote addr blocked, eg. by ip filter
+    Forbidden,
+    /// too many connections or requests from client, retry later
+    RateLimit,
 }
 
 pub struct RestResponse<B = ()> {
@@ -79,6 +89,7 @@ pub struct RestResponse<B = ()> {
     body: Option<B>,
     cookie_jar: Option<CookieJar>,
     s_cache: Option<Duration>,
+    retry_after: Option<Duration>,
 }
 
 impl<B> RestResponse<B> {
@@ -101,6 +112,7 @@ impl<B> RestResponse<B> {
             body: Some(body),
             cookie_jar: None,
             s_cache: None,
+            retry_after: None,
         }
     }
 
@@ -111,6 +123,7 @@ impl<B> RestResponse<B> {
             body: None,
             cookie_jar: None,
             s_cache: None,
+            retry_after: None,
         }
     }
 
@@ -123,6 +136,7 @@ impl<B> RestResponse<B> {
             body: None,
             cookie_jar: None,
             s_cache: None,
+            retry_after: None,
         }
     }
 
@@ -147,6 +161,25 @@ impl<B> RestResponse<B> {
     pub fn with_s_cache_seconds(self, seconds: u64) -> Self {
         self.with_s_cache(Duration::new(seconds, 0))
     }
+
+    pub fn with_retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = Some(retry_after);
+        self
+    }
+
+    pub fn map_body<C, F>(self, map: F) -> RestResponse<C>
+    where
+        F: FnOnce(B) -> C,
+    {
+        RestResponse {
+            status: self.status,
+            access_id: self.access_id,
+            body: self.body.map(map),
+            cookie_jar: self.cookie_jar,
+            s_cache: self.s_cache,
+            retry_after: self.retry_after,
+        }
+    }
 }
 
 impl<B: Default> RestResponse<B> {
@@ -166,10 +199,14 @@ where
             body,
             cookie_jar,
             s_cache,
+            retry_after,
         } = self;
 
         let status_code = match status {
             RestStatus::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+            RestStatus::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+            RestStatus::Forbidden => StatusCode::FORBIDDEN,
+            RestStatus::RateLimit => StatusCode::TOO_MANY_REQUESTS,
             _ => StatusCode::OK,
         };
 
@@ -185,7 +222,15 @@ where
             }),
         };
 
-        (status_code, cookie_jar, CachePart(s_cache), Json(json_body)).into_response()
+        let retry_after = retry_after.map(|v| TypedHeader(RetryAfter::delay(v)));
+        (
+            status_code,
+            cookie_jar,
+            CachePart(s_cache),
+            retry_after,
+            Json(json_body),
+        )
+            .into_response()
     }
 }
 
@@ -0,0 +1,395 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::{Context, Result};
+use axum::http::{HeaderName, Method, StatusCode};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{debug, info};
+use url::Url;
+use uuid::Uuid;
+
+use crate::scaffold::{
+    access_log::AccessLogId,
+    pretty::Pretty,
+    rest::{PagedRequest, RestStatus},
+};
+
+/// header used to pass the caller's access id to the remote service
+pub const FORWARD_ACCESS_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(Debug)]
+pub enum RestClientError {
+    /// remote answered with a non-ok envelope
+    Rest { status: RestStatus, access_id: Uuid },
+    /// request can't be sent or response body can't be read
+    Http(reqwest::Error),
+    /// response isn't a valid envelope
+    Decode {
+        http_status: StatusCode,
+        err: serde_json::Error,
+    },
+}
+
+impl RestClientError {
+    pub fn rest_status(&self) -> Option<RestStatus> {
+        match self {
+            Self::Rest { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn remote_access_id(&self) -> Option<Uuid> {
+        match self {
+            Self::Rest { access_id, .. } => Some(*access_id),
+            _ => None,
+        }
+    }
+}
+
+impl Display for RestClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rest { status, access_id } => {
+                write!(
+                    f,
+                    "remote rest status {:?}, remote access id {}",
+                    status, access_id
+                )
+            }
+            Self::Http(err) => write!(f, "http error: {}", err),
+            Self::Decode { http_status, err } => {
+                write!(f, "decode envelope error (http {}): {}", http_status, err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RestClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Rest { .. } => None,
+            Self::Http(err) => Some(err),
+            Self::Decode { err, .. } => Some(err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for RestClientError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Http(value)
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope<T> {
+    status: RestStatus,
+    access_id: Uuid,
+    body: Option<T>,
+}
+
+#[derive(Clone)]
+pub struct RestClient {
+    http: Client,
+    base: Url,
+}
+
+impl RestClient {
+    pub fn new(base: &str) -> Result<Self> {
+        let http = Client::builder().build().context("create http client")?;
+        Self::with_client(http, base)
+    }
+
+    /// base path is kept, `http://svc/api` is taken as `http://svc/api/`
+    pub fn with_client(http: Client, base: &str) -> Result<Self> {
+        let mut base = Url::parse(base).context("parse rest base url")?;
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        Ok(Self { http, base })
+    }
+
+    pub fn base(&self) -> &Url {
+        &self.base
+    }
+
+    /// `path` is relative to base even with a leading `/`
+    pub fn request(&self, method: Method, path: &str) -> Result<RestRequest> {
+        let url = self
+            .base
+            .join(path.trim_start_matches('/'))
+            .with_context(|| format!("join rest path {}", path))?;
+        Ok(RestRequest {
+            inner: self.http.request(method, url),
+        })
+    }
+
+    pub fn get(&self, path: &str) -> Result<RestRequest> {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> Result<RestRequest> {
+        self.request(Method::POST, path)
+    }
+}
+
+pub struct RestRequest {
+    inner: RequestBuilder,
+}
+
+impl RestRequest {
+    pub fn access_id(self, access_id: AccessLogId) -> Self {
+        Self {
+            inner: self
+                .inner
+                .header(FORWARD_ACCESS_ID_HEADER, access_id.uuid().to_string()),
+        }
+    }
+
+    pub fn query<Q: Serialize + ?Sized>(self, query: &Q) -> Self {
+        Self {
+            inner: self.inner.query(query),
+        }
+    }
+
+    pub fn paged<N: Serialize>(self, paged: &PagedRequest<N>) -> Self {
+        self.query(paged)
+    }
+
+    pub fn json<B: Serialize + ?Sized>(self, body: &B) -> Self {
+        Self {
+            inner: self.inner.json(body),
+        }
+    }
+
+    pub fn modify<F>(self, modify: F) -> Self
+    where
+        F: FnOnce(RequestBuilder) -> RequestBuilder,
+    {
+        Self {
+            inner: modify(self.inner),
+        }
+    }
+
+    pub async fn send<T: DeserializeOwned>(self) -> Result<T, RestClientError> {
+        let response = self.inner.send().await?;
+        let http_status = response.status();
+        let bytes = response.bytes().await?;
+
+        let envelope = serde_json::from_slice::<Envelope<T>>(&bytes)
+            .map_err(|err| RestClientError::Decode { http_status, err })?;
+
+        if envelope.status != RestStatus::Ok {
+            info!(
+                status = ?envelope.status,
+                remote_access_id = %envelope.access_id,
+                "remote rest failed"
+            );
+            return Err(RestClientError::Rest {
+                status: envelope.status,
+                access_id: envelope.access_id,
+            });
+        }
+
+        debug!(remote_access_id = %envelope.access_id, "remote rest ok");
+        match envelope.body {
+            Some(body) => Ok(body),
+            // `ok_none` omits body, which is valid for `()` and `Option<_>`
+            None => T::deserialize(serde_json::Value::Null).map_err(|err| {
+                info!(err=?Pretty(&err), "remote rest body missing");
+                RestClientError::Decode { http_status, err }
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{Extension, Router, routing::get};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        api::state::HostState,
+        scaffold::{
+            access_log::{AccessLog, AccessLogConfig},
+            connection_info::ConnectionInfo,
+            proxy_protocol::{ProxyListener, ProxyProtocolMode},
+            remote_addr::RemoteAddrConfig,
+            rest::{RestQuery, RestResponse},
+        },
+    };
+
+    #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
+    struct Item {
+        name: String,
+    }
+
+    /// host with access log on `127.0.0.1:0`, base url ends with `/api/`
+    async fn serve() -> RestClient {
+        let state = HostState::new(RemoteAddrConfig::default(), AccessLogConfig::for_test());
+        let api = Router::new()
+            .route(
+                "/item",
+                get(|Extension(id): Extension<AccessLogId>| async move {
+                    RestResponse::ok(
+                        id.uuid(),
+                        Item {
+                            name: "a".to_string(),
+                        },
+                    )
+                }),
+            )
+            .route(
+                "/none",
+                get(|Extension(id): Extension<AccessLogId>| async move {
+                    RestResponse::<()>::ok_none(id.uuid())
+                }),
+            )
+            .route(
+                "/fail",
+                get(|Extension(id): Extension<AccessLogId>| async move {
+                    RestResponse::<()>::fail(RestStatus::BadRequest, id.uuid())
+                }),
+            )
+            .route(
+                "/access_id",
+                get(|Extension(id): Extension<AccessLogId>| async move {
+                    RestResponse::ok(id.uuid(), id.uuid())
+                }),
+            )
+            .route(
+                "/paged",
+                get(
+                    |Extension(id): Extension<AccessLogId>,
+                     RestQuery(paged): RestQuery<PagedRequest<u32>>| async move {
+                        RestResponse::ok(id.uuid(), (paged.offset, paged.count))
+                    },
+                ),
+            );
+        let router = Router::new()
+            .nest("/api", api)
+            .layer(AccessLog::new(state.clone()))
+            .with_state(state);
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp_listener.local_addr().unwrap();
+        let listener = ProxyListener::new(
+            tcp_listener,
+            ProxyProtocolMode::Off,
+            Vec::new(),
+            Duration::from_secs(1),
+            Default::default(),
+            Default::default(),
+        );
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<ConnectionInfo>(),
+            )
+            .await
+            .unwrap();
+        });
+        RestClient::new(&format!("http://{}/api", addr)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn ok_body() {
+        let client = serve().await;
+        let item = client.get("item").unwrap().send::<Item>().await.unwrap();
+        assert_eq!(
+            item,
+            Item {
+                name: "a".to_string()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn ok_none_body() {
+        let client = serve().await;
+        client.get("none").unwrap().send::<()>().await.unwrap();
+        let item = client
+            .get("none")
+            .unwrap()
+            .send::<Option<Item>>()
+            .await
+            .unwrap();
+        assert_eq!(item, None);
+        let err = client
+            .get("none")
+            .unwrap()
+            .send::<Item>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RestClientError::Decode { .. }));
+    }
+
+    #[tokio::test]
+    async fn failed_envelope() {
+        let client = serve().await;
+        let access_id = Uuid::new_v4();
+        let err = client
+            .get("fail")
+            .unwrap()
+            .access_id(AccessLogId(access_id))
+            .send::<()>()
+            .await
+            .unwrap_err();
+        assert_eq!(err.rest_status(), Some(RestStatus::BadRequest));
+        assert_eq!(err.remote_access_id(), Some(access_id));
+    }
+
+    #[tokio::test]
+    async fn forwarded_access_id() {
+        let client = serve().await;
+        let access_id = Uuid::new_v4();
+        let remote = client
+            .get("access_id")
+            .unwrap()
+            .access_id(AccessLogId(access_id))
+            .send::<Uuid>()
+            .await
+            .unwrap();
+        assert_eq!(remote, access_id);
+    }
+
+    #[tokio::test]
+    async fn paged_query() {
+        let client = serve().await;
+        let paged = client
+            .get("/paged")
+            .unwrap()
+            .paged(&PagedRequest {
+                offset: Some(20u32),
+                count: Some(10),
+            })
+            .send::<(Option<u32>, Option<u32>)>()
+            .await
+            .unwrap();
+        assert_eq!(paged, (Some(20), Some(10)));
+
+        let paged = client
+            .get("/paged")
+            .unwrap()
+            .paged(&PagedRequest::<u32> {
+                offset: None,
+                count: Some(10),
+            })
+            .send::<(Option<u32>, Option<u32>)>()
+            .await
+            .unwrap();
+        assert_eq!(paged, (None, Some(10)));
+    }
+
+    #[test]
+    fn base_path_kept() {
+        let client = RestClient::new("http://svc/api").unwrap();
+        for path in ["users", "/users"] {
+            let request = client.get(path).unwrap().inner.build().unwrap();
+            assert_eq!(request.url().as_str(), "http://svc/api/users");
+        }
+    }
+}
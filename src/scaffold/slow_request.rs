@@ -0,0 +1,68 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::{Context, Error, Result, anyhow};
+use serde::Deserialize;
+
+/// per route latency budget, `{route}={millis}`, eg. `/users/{id}=500`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RouteBudget {
+    pub route: String,
+    pub budget: Duration,
+}
+
+impl FromStr for RouteBudget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((route, millis)) = s.rsplit_once('=') else {
+            return Err(anyhow!(
+                "route budget should be {{route}}={{millis}}: {}",
+                s
+            ));
+        };
+        let millis = millis
+            .trim()
+            .parse::<u64>()
+            .with_context(|| format!("parse route budget millis: {}", s))?;
+        Ok(Self {
+            route: route.trim().to_string(),
+            budget: Duration::from_millis(millis),
+        })
+    }
+}
+
+impl TryFrom<String> for RouteBudget {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self> {
+        value.parse()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SlowRequestPolicy {
+    global: Option<Duration>,
+    routes: HashMap<String, Duration>,
+    mid_flight: bool,
+}
+
+impl SlowRequestPolicy {
+    pub fn new(global: Option<Duration>, routes: Vec<RouteBudget>, mid_flight: bool) -> Self {
+        Self {
+            global,
+            routes: routes.into_iter().map(|v| (v.route, v.budget)).collect(),
+            mid_flight,
+        }
+    }
+
+    /// route budget overrides global one
+    pub fn budget(&self, route: &str) -> Option<Duration> {
+        self.routes.get(route).copied().or(self.global)
+    }
+
+    /// warn once while request is still running past its budget
+    pub fn mid_flight(&self) -> bool {
+        self.mid_flight
+    }
+}
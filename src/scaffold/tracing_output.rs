@@ -4,15 +4,22 @@ use tracing::level_filters::LevelFilter;
 use tracing_appender::{non_blocking::WorkerGuard, rolling::Rotation};
 use tracing_loki::BackgroundTaskController;
 use tracing_subscriber::{
-    EnvFilter, Layer, filter::Filtered, layer::SubscriberExt, util::SubscriberInitExt,
+    EnvFilter, Layer,
+    filter::{Filtered, filter_fn},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
 };
 use url::Url;
 
+use crate::scaffold::access_sink::{ACCESS_TARGET, AccessSinkLayer, AccessSinkOptions};
+
+/// access events only go to access sink, if any
 pub fn setup(
     output_dir: &str,
     filter: &str,
     loki: Option<&str>,
-) -> Result<(WorkerGuard, Option<BackgroundTaskController>)> {
+    access: Option<AccessSinkOptions>,
+) -> Result<(Vec<WorkerGuard>, Option<BackgroundTaskController>)> {
     let file_rolling = tracing_appender::rolling::Builder::new()
         .filename_prefix("host")
         .filename_suffix("log")
@@ -22,10 +29,35 @@ pub fn setup(
         .context("create tracing file rolling output")?;
 
     let (file_rolling, guard) = tracing_appender::non_blocking(file_rolling);
+    let mut guards = vec![guard];
+
+    let access_layer = match access {
+        Some(access) => {
+            let access_rolling = tracing_appender::rolling::Builder::new()
+                .filename_prefix("access")
+                .filename_suffix("log")
+                .rotation(access.rotation.into())
+                .max_log_files(access.max_files)
+                .build(output_dir)
+                .context("create access log file rolling output")?;
+            let (access_rolling, guard) = tracing_appender::non_blocking(access_rolling);
+            guards.push(guard);
+            Some(
+                AccessSinkLayer::new(access.format, access_rolling)
+                    .with_filter(filter_fn(|meta| meta.target() == ACCESS_TARGET)),
+            )
+        }
+        None => None,
+    };
 
     let env_filter = EnvFilter::builder()
         .with_default_directive(LevelFilter::DEBUG.into())
-        .parse_lossy(filter);
+        .parse_lossy(filter)
+        .add_directive(
+            format!("{}=off", ACCESS_TARGET)
+                .parse()
+                .context("parse access target directive")?,
+        );
 
     let registry = tracing_subscriber::registry()
         .with(tracing_subscriber::fmt::layer().with_filter(env_filter.clone()))
@@ -35,7 +67,8 @@ pub fn setup(
                 .with_ansi(false)
                 .with_writer(file_rolling)
                 .with_filter(env_filter.clone()),
-        );
+        )
+        .with(access_layer);
 
     let controller = match loki {
         Some(loki) => {
@@ -57,5 +90,5 @@ pub fn setup(
         }
     };
 
-    Ok((guard, controller))
+    Ok((guards, controller))
 }